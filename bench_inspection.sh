@@ -0,0 +1,11 @@
+#!/usr/bin/env zsh
+
+# Times file inspection over the fixture corpus, both reading each file into memory first and
+# streaming it.
+# Usage: ./bench_inspection.sh [iterations] [corpus dir]
+
+export ITERATIONS="${1:-5}"
+export CORPUS="${2:-fixtures/bench-inspection}"
+
+cargo build --release
+./target/release/pypi-import-test bench-inspection "$CORPUS" --iterations="$ITERATIONS" | tee -a bench_output.txt
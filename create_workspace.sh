@@ -38,6 +38,6 @@ echo "creating URLs"
 #
 #echo "running partitions"
 export RUST_LOG=warn
-fd . "$URLS_DIR" | shuf | parallel -u --progress --joblog=job.log --eta -P "$CONCURRENCY" -I{} "./target/release/pypi-import-test from-json {} $TEMP_DIR/ $PARTITIONS_DIR/ $TEMPLATE_DIR 2>&1"
+fd -E manifest.json -E errors.json . "$URLS_DIR" | shuf | parallel -u --progress --joblog=job.log --eta -P "$CONCURRENCY" -I{} "./target/release/pypi-import-test from-json {} $TEMP_DIR/ $PARTITIONS_DIR/ $TEMPLATE_DIR 2>&1"
 
 #cargo run -q --release -- from-json $URLS_DIR/chunk_0.json $TEMP_DIR/chunk_0/ $PARTITIONS_DIR/chunk_0/ $TEMPLATE_DIR
@@ -4,9 +4,14 @@ use std::io::{Cursor, Read};
 
 use bzip2::read::BzDecoder;
 
-use crate::file_inspection::{skip_archive_entry, write_archive_entry_to_odb};
+use crate::distribution::ArchiveFormat;
+use crate::file_inspection::{
+    sha256_hex, skip_archive_entry, write_archive_entry_to_odb, Inspection, InspectionOptions,
+    SkipReason, SkippedFile, WrittenFile,
+};
+use crate::release_metadata::is_metadata_file;
 use flate2::read::GzDecoder;
-use git2::{Odb, Oid};
+use git2::Odb;
 
 use tar::{Archive, Entries};
 use zip::read::read_zipfile_from_stream;
@@ -20,44 +25,67 @@ pub enum PackageArchive {
 }
 
 impl PackageArchive {
-    pub fn new(extension: &str, reader: PackageReader) -> Option<Self> {
-        match extension {
-            "egg" | "zip" | "whl" | "exe" => Some(PackageArchive::Zip(reader)),
-            "gz" => {
+    pub fn new(format: ArchiveFormat, reader: PackageReader) -> Option<Self> {
+        match format {
+            ArchiveFormat::Zip | ArchiveFormat::Exe => Some(PackageArchive::Zip(reader)),
+            ArchiveFormat::TarGz => {
                 let tar = GzDecoder::new(reader);
                 let archive = Archive::new(tar);
                 Some(PackageArchive::TarGz(Box::new(archive)))
             }
-            "bz2" => {
+            ArchiveFormat::TarBz2 => {
                 let tar = BzDecoder::new(reader);
                 let archive = Archive::new(tar);
                 Some(PackageArchive::TarBz(Box::new(archive)))
             }
-            _ => None,
+            ArchiveFormat::TarXz | ArchiveFormat::Tar | ArchiveFormat::Msi => None,
         }
     }
 
-    pub fn all_items<'a>(&'a mut self, odb: &'a Odb<'a>) -> PackageEnumIterator<'a> {
+    pub fn all_items<'a>(
+        &'a mut self,
+        odb: &'a Odb<'a>,
+        options: &'a InspectionOptions,
+    ) -> PackageEnumIterator<'a> {
         match self {
-            PackageArchive::Zip(z) => PackageEnumIterator::Zip(z, odb),
-            PackageArchive::TarGz(t) => PackageEnumIterator::TarGz(t.entries().unwrap(), odb),
-            PackageArchive::TarBz(t) => PackageEnumIterator::TarBz(t.entries().unwrap(), odb),
+            PackageArchive::Zip(z) => PackageEnumIterator::Zip(z, odb, options),
+            PackageArchive::TarGz(t) => {
+                PackageEnumIterator::TarGz(t.entries().unwrap(), odb, options)
+            }
+            PackageArchive::TarBz(t) => {
+                PackageEnumIterator::TarBz(t.entries().unwrap(), odb, options)
+            }
         }
     }
 }
 
+pub enum ArchiveEntry {
+    Imported(String, WrittenFile),
+    Skipped(SkippedFile),
+    /// Packaging metadata, which isn't imported but is kept so it can be parsed.
+    Metadata(SkippedFile, Vec<u8>),
+}
+
 pub enum PackageEnumIterator<'a> {
-    Zip(&'a mut PackageReader, &'a Odb<'a>),
-    TarGz(Entries<'a, GzDecoder<PackageReader>>, &'a Odb<'a>),
-    TarBz(Entries<'a, BzDecoder<PackageReader>>, &'a Odb<'a>),
+    Zip(&'a mut PackageReader, &'a Odb<'a>, &'a InspectionOptions),
+    TarGz(
+        Entries<'a, GzDecoder<PackageReader>>,
+        &'a Odb<'a>,
+        &'a InspectionOptions,
+    ),
+    TarBz(
+        Entries<'a, BzDecoder<PackageReader>>,
+        &'a Odb<'a>,
+        &'a InspectionOptions,
+    ),
 }
 
 impl<'a> Iterator for PackageEnumIterator<'a> {
-    type Item = anyhow::Result<(String, Oid)>;
+    type Item = anyhow::Result<ArchiveEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            PackageEnumIterator::Zip(v, odb) => loop {
+            PackageEnumIterator::Zip(v, odb, options) => loop {
                 return match read_zipfile_from_stream(v) {
                     Ok(z) => match z {
                         None => None,
@@ -65,26 +93,16 @@ impl<'a> Iterator for PackageEnumIterator<'a> {
                             if !z.is_file() {
                                 continue;
                             }
-                            if skip_archive_entry(z.name(), z.size()) {
-                                continue;
-                            }
                             let name = z.name().to_string();
                             let size = z.size();
-                            let write_result = write_archive_entry_to_odb(&name, size, &mut z, odb);
-                            match write_result {
-                                Ok(v) => match v {
-                                    None => continue,
-                                    Some(oid) => Some(Ok((name, oid))),
-                                },
-                                Err(e) => Some(Err(e)),
-                            }
+                            Some(handle_entry(name, size, &mut z, odb, options))
                         }
                     },
                     Err(_) => None,
                 };
             },
-            PackageEnumIterator::TarGz(t, odb) => find_tar_item(t, odb),
-            PackageEnumIterator::TarBz(t, odb) => find_tar_item(t, odb),
+            PackageEnumIterator::TarGz(t, odb, options) => find_tar_item(t, odb, options),
+            PackageEnumIterator::TarBz(t, odb, options) => find_tar_item(t, odb, options),
         }
     }
 }
@@ -92,46 +110,70 @@ impl<'a> Iterator for PackageEnumIterator<'a> {
 fn find_tar_item(
     items: &mut Entries<impl Read>,
     odb: &Odb,
-) -> Option<anyhow::Result<(String, Oid)>> {
-    let iterator = items.into_iter().flatten().flat_map(|v| {
-        let path = v
-            .path()?
-            .to_str()
-            .ok_or(anyhow!("Error converting path to string"))?
-            .to_string();
-        let size = v.size();
-        Ok::<_, anyhow::Error>((path, size, v))
-    });
-    find_item(iterator, odb)
+    options: &InspectionOptions,
+) -> Option<anyhow::Result<ArchiveEntry>> {
+    let iterator = items
+        .into_iter()
+        .flatten()
+        .filter(|v| v.header().entry_type().is_file())
+        .flat_map(|v| {
+            let path = v
+                .path()?
+                .to_str()
+                .ok_or(anyhow!("Error converting path to string"))?
+                .to_string();
+            let size = v.size();
+            Ok::<_, anyhow::Error>((path, size, v))
+        });
+    find_item(iterator, odb, options)
 }
 
 fn find_item(
     mut items: impl Iterator<Item = (String, u64, impl Read)>,
     odb: &Odb,
-) -> Option<anyhow::Result<(String, Oid)>> {
-    while let Some((path, size, mut reader)) = items.find(|(_, size, _)| *size != 0) {
-        match handle_tar_gz(path, size, &mut reader, odb) {
-            Ok(v) => match v {
-                None => continue,
-                Some(v) => return Some(Ok(v)),
-            },
-            Err(e) => return Some(Err(e)),
-        }
-    }
-    None
+    options: &InspectionOptions,
+) -> Option<anyhow::Result<ArchiveEntry>> {
+    let (path, size, mut reader) = items.next()?;
+    Some(handle_entry(path, size, &mut reader, odb, options))
 }
 
-fn handle_tar_gz(
+fn handle_entry(
     path: String,
     size: u64,
     mut reader: impl Read,
     odb: &Odb,
-) -> anyhow::Result<Option<(String, Oid)>> {
-    if skip_archive_entry(&path, size) {
-        return Ok(None);
+    options: &InspectionOptions,
+) -> anyhow::Result<ArchiveEntry> {
+    // Entries we don't import are still hashed, so the release manifest can describe exactly
+    // what was left out.
+    if let Some(reason) = skip_archive_entry(&path, size) {
+        if reason == SkipReason::NotPython && is_metadata_file(&path) {
+            let mut contents = Vec::with_capacity(size as usize);
+            reader.read_to_end(&mut contents)?;
+            let sha256 = sha256_hex(&contents, &mut std::io::empty())?;
+            let skipped = SkippedFile {
+                path,
+                size,
+                sha256,
+                reason,
+            };
+            return Ok(ArchiveEntry::Metadata(skipped, contents));
+        }
+        let sha256 = sha256_hex(&[], &mut reader)?;
+        return Ok(ArchiveEntry::Skipped(SkippedFile {
+            path,
+            size,
+            sha256,
+            reason,
+        }));
     }
-    if let Some(oid) = write_archive_entry_to_odb(&path, size, &mut reader, odb)? {
-        return Ok(Some((path, oid)));
+    match write_archive_entry_to_odb(&path, size, &mut reader, odb, options)? {
+        Inspection::Written(file) => Ok(ArchiveEntry::Imported(path, file)),
+        Inspection::Skipped { reason, sha256 } => Ok(ArchiveEntry::Skipped(SkippedFile {
+            path,
+            size,
+            sha256,
+            reason,
+        })),
     }
-    Ok(None)
 }
@@ -0,0 +1,81 @@
+use crate::archive::{ArchiveEntry, PackageArchive};
+use crate::distribution::ArchiveFormat;
+use crate::file_inspection::InspectionOptions;
+use git2::Repository;
+use jwalk::WalkDir;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Time how long it takes to inspect every archive in `corpus`, writing into a throwaway
+/// in-memory object database. Archives are read up front so only the inspection is measured.
+/// Each run is done twice: reading each file into memory first, as imports used to, and streaming
+/// it.
+pub fn bench_inspection(
+    corpus: PathBuf,
+    iterations: usize,
+    options: &InspectionOptions,
+) -> anyhow::Result<()> {
+    let archives: Vec<(ArchiveFormat, Vec<u8>)> = WalkDir::new(&corpus)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .flat_map(|e| {
+            let path = e.path();
+            let format = ArchiveFormat::from_filename(path.file_name()?.to_str()?)?;
+            Some(fs::read(&path).map(|data| (format, data)))
+        })
+        .collect::<Result<_, _>>()?;
+    let archive_bytes: usize = archives.iter().map(|(_, data)| data.len()).sum();
+
+    let total_bytes = (archive_bytes * iterations) as f64;
+    for (mode, buffered) in [("buffered", true), ("streaming", false)] {
+        let options = InspectionOptions {
+            buffered,
+            ..options.clone()
+        };
+        let (elapsed, imported, skipped) = time_inspection(&archives, iterations, &options)?;
+        println!(
+            "{mode}: {} archives x {iterations}: {:.2}s, {:.1} MB/s, {:.0} imported files/s, {:.0} skipped files/s",
+            archives.len(),
+            elapsed,
+            total_bytes / elapsed / 1024.0 / 1024.0,
+            imported as f64 / elapsed,
+            skipped as f64 / elapsed,
+        );
+    }
+    Ok(())
+}
+
+fn time_inspection(
+    archives: &[(ArchiveFormat, Vec<u8>)],
+    iterations: usize,
+    options: &InspectionOptions,
+) -> anyhow::Result<(f64, usize, usize)> {
+    let repo_path = std::env::temp_dir().join(format!("bench-inspection-{}", std::process::id()));
+    let repo = Repository::init(&repo_path)?;
+    let odb = repo.odb()?;
+    let _mempack_backend = odb.add_new_mempack_backend(3)?;
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for (format, data) in archives {
+            let Some(mut archive) =
+                PackageArchive::new(*format, std::io::Cursor::new(data.clone()))
+            else {
+                continue;
+            };
+            for entry in archive.all_items(&odb, options) {
+                match entry? {
+                    ArchiveEntry::Imported(_, _) => imported += 1,
+                    ArchiveEntry::Skipped(_) | ArchiveEntry::Metadata(_, _) => skipped += 1,
+                }
+            }
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    fs::remove_dir_all(&repo_path)?;
+    Ok((elapsed, imported, skipped))
+}
@@ -1,10 +1,14 @@
-use git2::{ObjectType, Repository};
+use git2::{BranchType, ObjectType, Repository, Signature};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use std::fs;
 
-use crate::job::CommitMessage;
+use crate::create_urls::FileMetadata;
+use crate::distribution::DistributionFile;
+use crate::job::{CommitMessage, FileStatus};
+use crate::release_metadata::ReleaseMetadata;
+use crate::version::Version;
 
 use anyhow::Context;
 
@@ -12,7 +16,7 @@ use chrono::prelude::*;
 
 use itertools::Itertools;
 use log::warn;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tinytemplate::TinyTemplate;
 use url::Url;
@@ -21,8 +25,30 @@ use url::Url;
 pub struct JsonIndexEntry {
     pub name: String,
     pub version: String,
+    /// Alpha, beta, release candidate or development releases.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prerelease: bool,
+    /// Versions that aren't valid under PEP 440, which sort before every valid version.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub invalid_version: bool,
     pub path: PathBuf,
-    pub uploaded_on: DateTime<Utc>,
+    /// Missing for files from simple indexes that don't publish upload times.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploaded_on: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<String>,
+    #[serde(flatten)]
+    pub metadata: FileMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distribution: Option<DistributionFile>,
+    #[serde(default, skip_serializing_if = "ReleaseMetadata::is_empty")]
+    pub release_metadata: ReleaseMetadata,
+    #[serde(default, skip_serializing_if = "FileStatus::is_available")]
+    pub status: FileStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -35,7 +61,61 @@ pub struct JsonIndex {
 
 const FILE_MODE_TREE: i32 = 0o040000;
 
-pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Result<()> {
+/// Kept on a combined repository's main branch next to index.json, listing the files marked as
+/// removed by `mark-removed` and why. Combined repositories are rebuilt from the partition commits,
+/// which know nothing about these marks, so the list has to be passed back to `merge-branches`.
+pub const REMOVED_FILES_PATH: &str = "removed.json";
+
+type RemovedFiles = BTreeMap<String, String>;
+
+fn load_removed_files(paths: &[PathBuf]) -> anyhow::Result<RemovedFiles> {
+    let mut removed = RemovedFiles::new();
+    for path in paths {
+        let contents =
+            fs::read(path).with_context(|| format!("Error reading {}", path.display()))?;
+        let files: RemovedFiles = serde_json::from_slice(&contents)
+            .with_context(|| format!("Error parsing {}", path.display()))?;
+        removed.extend(files);
+    }
+    Ok(removed)
+}
+
+/// A file imported by one partition can be found to be removed by a later one, which commits it
+/// again just to record that. Each path keeps the entry that was actually imported, as that has
+/// the metadata read from the archive, with the furthest along status of any of its entries.
+fn deduplicate_entries(entries: Vec<JsonIndexEntry>) -> Vec<JsonIndexEntry> {
+    let mut deduplicated: Vec<JsonIndexEntry> = Vec::with_capacity(entries.len());
+    let mut positions: HashMap<PathBuf, usize> = HashMap::new();
+    for entry in entries {
+        let idx = match positions.entry(entry.path.clone()) {
+            Entry::Vacant(e) => {
+                e.insert(deduplicated.len());
+                deduplicated.push(entry);
+                continue;
+            }
+            Entry::Occupied(e) => *e.get(),
+        };
+        let existing = &mut deduplicated[idx];
+        let (status, status_reason) = if entry.status > existing.status {
+            (entry.status, entry.status_reason.clone())
+        } else {
+            (existing.status, existing.status_reason.take())
+        };
+        if existing.status == FileStatus::Removed && entry.status != FileStatus::Removed {
+            *existing = entry;
+        }
+        existing.status = status;
+        existing.status_reason = status_reason;
+    }
+    deduplicated
+}
+
+pub fn merge_all_branches(
+    into: PathBuf,
+    mut repos: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let removed = load_removed_files(&removed)?;
     let repository_partition_index = into.file_name().unwrap().to_str().unwrap();
     git2::opts::strict_object_creation(false);
     git2::opts::strict_hash_verification(false);
@@ -157,7 +237,10 @@ pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Res
         if current_mark > 1 {
             println!("from :{}", current_mark - 1);
         }
-        println!("M 040000 {} {}", commit.tree_id(), message.path.display());
+        // Removed files have an empty tree, and only exist to be listed in the index.
+        if message.status != FileStatus::Removed {
+            println!("M 040000 {} {}", commit.tree_id(), message.path.display());
+        }
         println!();
 
         if (current_mark % 10_000) == 0 {
@@ -165,15 +248,28 @@ pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Res
             println!();
         }
 
-        let time = DateTime::<Utc>::from_utc(
-            NaiveDateTime::from_timestamp_opt(commit.time().seconds(), 0).unwrap(),
-            Utc,
-        );
+        // Files without an upload time are committed at the epoch.
+        let time = (commit.time().seconds() != 0).then(|| {
+            DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp_opt(commit.time().seconds(), 0).unwrap(),
+                Utc,
+            )
+        });
+        let version = Version::parse(&message.version);
         let entry = JsonIndexEntry {
             name: message.name,
+            prerelease: version.is_prerelease(),
+            invalid_version: version.is_legacy(),
             version: message.version,
             path: message.path,
             uploaded_on: time,
+            aliases: message.aliases,
+            metadata: message.metadata,
+            distribution: message.distribution,
+            release_metadata: message.release_metadata,
+            siblings: message.siblings,
+            status: message.status,
+            status_reason: message.status_reason,
         };
 
         match packages_index.entry(entry.name.to_string()) {
@@ -184,15 +280,36 @@ pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Res
         }
     }
 
+    for entries in packages_index.values_mut() {
+        *entries = deduplicate_entries(std::mem::take(entries));
+    }
+
+    for entry in packages_index.values_mut().flatten() {
+        let Some(reason) = removed.get(entry.path.to_str().unwrap()) else {
+            continue;
+        };
+        if entry.status != FileStatus::Removed {
+            entry.status = FileStatus::Removed;
+            entry.status_reason = Some(reason.clone());
+        }
+    }
+
+    // Commits are in upload order, but a package's history reads better in version order. Files
+    // of the same version stay in upload order.
+    for entries in packages_index.values_mut() {
+        entries.sort_by_cached_key(|e| (Version::parse(&e.version), e.uploaded_on));
+    }
+
     let total_projects = packages_index.len();
     let total_releases = packages_index.values().flatten().count();
+    // A repository of nothing but undated files is left at the epoch.
     let (min_release_time, max_release_time) = packages_index
         .values()
         .flatten()
-        .map(|e| e.uploaded_on)
+        .filter_map(|e| e.uploaded_on)
         .minmax()
         .into_option()
-        .unwrap();
+        .unwrap_or_default();
     let top_projects_by_count = packages_index
         .iter()
         .map(|(name, items)| (name, items.len()))
@@ -252,6 +369,19 @@ pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Res
     println!("{index_json}");
     let index_json_mark = current_mark;
 
+    // Carry the removed files forward, so the next rebuild can be given them again.
+    let removed_mark = if removed.is_empty() {
+        None
+    } else {
+        let removed_json = serde_json::to_string_pretty(&removed)?;
+        current_mark += 1;
+        println!("blob");
+        println!("mark :{current_mark}");
+        println!("data {}", removed_json.len());
+        println!("{removed_json}");
+        Some(current_mark)
+    };
+
     println!("commit refs/heads/main");
     println!(
         "author Tom Forbes <tom@tomforb.es> {} +0000",
@@ -272,9 +402,125 @@ pub fn merge_all_branches(into: PathBuf, mut repos: Vec<PathBuf>) -> anyhow::Res
     println!("{}", commit_message);
     println!("M 100644 :{} README.md", readme_mark);
     println!("M 100644 :{} index.json", index_json_mark);
+    if let Some(removed_mark) = removed_mark {
+        println!("M 100644 :{removed_mark} {REMOVED_FILES_PATH}");
+    }
     println!();
 
     println!("done");
 
     Ok(())
 }
+
+/// Mark files in a combined repository as removed from PyPI. The updated index.json is committed
+/// on top of `main`, leaving the imported code and its history alone.
+pub fn mark_removed(repo_path: PathBuf, paths: Vec<String>, reason: String) -> anyhow::Result<()> {
+    let repo = Repository::open(&repo_path)?;
+    let parent = repo
+        .find_branch("main", BranchType::Local)?
+        .get()
+        .peel_to_commit()?;
+    let tree = parent.tree()?;
+    let index_blob = repo.find_blob(tree.get_path(Path::new("index.json"))?.id())?;
+    let mut index: JsonIndex = serde_json::from_slice(index_blob.content())?;
+
+    let mut removed: RemovedFiles = match tree.get_path(Path::new(REMOVED_FILES_PATH)) {
+        Ok(entry) => serde_json::from_slice(repo.find_blob(entry.id())?.content())?,
+        Err(_) => RemovedFiles::new(),
+    };
+
+    let mut remaining: HashSet<&str> = paths.iter().map(String::as_str).collect();
+    let mut marked = 0;
+    for entry in index.entries.values_mut().flatten() {
+        let path = entry.path.to_str().unwrap();
+        if remaining.remove(path) {
+            entry.status = FileStatus::Removed;
+            entry.status_reason = Some(reason.clone());
+            removed.insert(path.to_string(), reason.clone());
+            marked += 1;
+        }
+    }
+    for path in remaining {
+        warn!("{path} is not in the index");
+    }
+    if marked == 0 {
+        return Ok(());
+    }
+
+    let index_oid = repo.blob(serde_json::to_string(&index)?.as_bytes())?;
+    let removed_oid = repo.blob(serde_json::to_string_pretty(&removed)?.as_bytes())?;
+    let mut tree_builder = repo.treebuilder(Some(&tree))?;
+    tree_builder.insert("index.json", index_oid, 0o100644)?;
+    tree_builder.insert(REMOVED_FILES_PATH, removed_oid, 0o100644)?;
+    let new_tree = repo.find_tree(tree_builder.write()?)?;
+
+    let signature = Signature::now("Tom Forbes", "tom@tomforb.es")?;
+    let message = format!("Mark {marked} files as removed: {reason}");
+    repo.commit(
+        Some("refs/heads/main"),
+        &signature,
+        &signature,
+        &message,
+        &new_tree,
+        &[&parent],
+    )?;
+    println!("{message}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, status: &str, python_version: &str) -> JsonIndexEntry {
+        serde_json::from_value(serde_json::json!({
+            "name": "demo",
+            "version": "1.0",
+            "path": path,
+            "status": status,
+            "status_reason": format!("{status} reason"),
+            "python_version": python_version,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn removed_after_import() {
+        for entries in [
+            vec![
+                entry("demo/demo-1.0.tar.gz", "available", "source"),
+                entry("demo/demo-1.0.tar.gz", "removed", "unknown"),
+            ],
+            vec![
+                entry("demo/demo-1.0.tar.gz", "removed", "unknown"),
+                entry("demo/demo-1.0.tar.gz", "available", "source"),
+            ],
+        ] {
+            let [entry] = &deduplicate_entries(entries)[..] else {
+                panic!("not deduplicated");
+            };
+            assert_eq!(entry.status, FileStatus::Removed);
+            assert_eq!(entry.status_reason.as_deref(), Some("removed reason"));
+            assert_eq!(entry.metadata.python_version.as_deref(), Some("source"));
+        }
+    }
+
+    #[test]
+    fn distinct_paths() {
+        let entries = vec![
+            entry("demo/demo-1.0.tar.gz", "available", "source"),
+            entry("demo/demo-1.0-py3-none-any.whl", "yanked", "py3"),
+        ];
+        let paths: Vec<_> = deduplicate_entries(entries)
+            .into_iter()
+            .map(|e| (e.path, e.status))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("demo/demo-1.0.tar.gz".into(), FileStatus::Available),
+                ("demo/demo-1.0-py3-none-any.whl".into(), FileStatus::Yanked),
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::distribution::{DistributionFile, DistributionKind};
+use crate::job::{CommitMessage, FileStatus, ReleaseManifest, RELEASE_MANIFEST_PATH};
+use git2::{ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use itertools::Itertools;
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Default)]
+pub struct ConsistencyReport {
+    /// Releases with both an sdist and at least one wheel.
+    pub releases: usize,
+    pub wheels_compared: usize,
+    /// Releases with wheels that couldn't be compared, because no sdist was imported.
+    pub releases_without_sdist: usize,
+    /// Every wheel with files that don't match the sdist.
+    pub inconsistent: Vec<WheelComparison>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WheelComparison {
+    pub name: String,
+    pub version: String,
+    pub sdist: String,
+    pub wheel: String,
+    /// Where the wheel's files live in the sdist, for each top level directory in the wheel.
+    pub layout: BTreeMap<String, String>,
+    pub matching: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub only_in_wheel: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub differing: Vec<String>,
+    /// Files in the wheel whose sdist counterpart exists but wasn't imported, so can't be compared.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_in_sdist: Vec<String>,
+}
+
+impl WheelComparison {
+    fn is_consistent(&self) -> bool {
+        self.only_in_wheel.is_empty() && self.differing.is_empty()
+    }
+}
+
+struct Release<'r> {
+    message: CommitMessage,
+    kind: Option<DistributionKind>,
+    tree: Tree<'r>,
+}
+
+/// Compare the Python files in each release's wheels with those in its sdist, reporting any that
+/// only appear in a wheel or have different contents. Code that is shipped in a wheel but not in
+/// the sdist it was supposedly built from is a favourite trick of supply chain attacks.
+///
+/// Works on both partition repositories and combined repositories.
+pub fn check_consistency(repo_path: PathBuf) -> anyhow::Result<()> {
+    let repo = Repository::open(repo_path)?;
+    let odb = repo.odb()?;
+    let mut commits = vec![];
+    odb.foreach(|oid| {
+        if let Ok((_, ObjectType::Commit)) = odb.read_header(*oid) {
+            commits.push(*oid);
+        }
+        true
+    })?;
+
+    let mut releases: HashMap<(String, String), Vec<Release>> = HashMap::new();
+    // Combined repositories also contain the partition commits their packs were copied from, so
+    // the same file can be committed twice.
+    let mut seen = HashSet::new();
+    for oid in commits {
+        let commit = repo.find_commit(oid)?;
+        // The README and index commits in combined repositories aren't releases.
+        let Ok(message) =
+            serde_json::from_str::<CommitMessage>(commit.message().unwrap_or_default())
+        else {
+            continue;
+        };
+        if message.status == FileStatus::Removed
+            || !seen.insert((
+                message.name.clone(),
+                message.version.clone(),
+                message.file.clone(),
+            ))
+        {
+            continue;
+        }
+        // Partition repositories commit each release's tree at the root, combined repositories
+        // add it at the release's path.
+        let root = commit.tree()?;
+        let tree = match root.get_path(&message.path) {
+            Ok(entry) => repo.find_tree(entry.id())?,
+            Err(_) => root,
+        };
+        let kind = message
+            .distribution
+            .clone()
+            .or_else(|| DistributionFile::parse(&message.file, &message.name))
+            .map(|d| d.kind);
+        releases
+            .entry((message.name.clone(), message.version.clone()))
+            .or_default()
+            .push(Release {
+                message,
+                kind,
+                tree,
+            });
+    }
+
+    let mut report = ConsistencyReport::default();
+    for ((name, version), files) in releases.into_iter().sorted_by(|a, b| a.0.cmp(&b.0)) {
+        let (sdists, wheels): (Vec<_>, Vec<_>) = files
+            .iter()
+            .filter(|f| {
+                matches!(
+                    f.kind,
+                    Some(DistributionKind::Sdist | DistributionKind::Wheel)
+                )
+            })
+            .partition(|f| f.kind == Some(DistributionKind::Sdist));
+        if wheels.is_empty() {
+            continue;
+        }
+        let Some(sdist) = sdists.first() else {
+            report.releases_without_sdist += 1;
+            continue;
+        };
+        report.releases += 1;
+
+        let sdist_files = python_files(&sdist.tree)?;
+        let sdist_skipped = skipped_files(&repo, &sdist.tree);
+        for wheel in wheels {
+            report.wheels_compared += 1;
+            let comparison = compare(&name, &version, sdist, &sdist_files, &sdist_skipped, wheel)?;
+            if !comparison.is_consistent() {
+                report.inconsistent.push(comparison);
+            }
+        }
+    }
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn compare(
+    name: &str,
+    version: &str,
+    sdist: &Release,
+    sdist_files: &HashMap<String, Oid>,
+    sdist_skipped: &[String],
+    wheel: &Release,
+) -> anyhow::Result<WheelComparison> {
+    let wheel_files = python_files(&wheel.tree)?;
+    let layout = layout(&wheel_files, sdist_files);
+
+    let mut comparison = WheelComparison {
+        name: name.to_string(),
+        version: version.to_string(),
+        sdist: sdist.message.file.clone(),
+        wheel: wheel.message.file.clone(),
+        layout: layout.clone(),
+        matching: 0,
+        only_in_wheel: vec![],
+        differing: vec![],
+        skipped_in_sdist: vec![],
+    };
+    for (path, oid) in wheel_files.iter().sorted() {
+        let prefix = layout.get(top_level(path)).map_or("", String::as_str);
+        let sdist_path = format!("{prefix}{path}");
+        match sdist_files.get(&sdist_path) {
+            Some(sdist_oid) if sdist_oid == oid => comparison.matching += 1,
+            Some(_) => comparison.differing.push(path.clone()),
+            None if sdist_skipped.iter().any(|s| is_suffix(s, &sdist_path)) => {
+                comparison.skipped_in_sdist.push(path.clone())
+            }
+            None => comparison.only_in_wheel.push(path.clone()),
+        }
+    }
+    Ok(comparison)
+}
+
+// Where each of the wheel's top level directories lives in the sdist: at the root, under `src/`
+// or wherever most of its files are found. Single top level directories are also stripped when
+// releases are imported, which this takes care of too.
+fn layout(
+    wheel_files: &HashMap<String, Oid>,
+    sdist_files: &HashMap<String, Oid>,
+) -> BTreeMap<String, String> {
+    let mut by_filename: HashMap<&str, Vec<&str>> = HashMap::new();
+    for path in sdist_files.keys() {
+        let filename = path.rsplit('/').next().unwrap();
+        by_filename.entry(filename).or_default().push(path);
+    }
+
+    let mut prefixes: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for path in wheel_files.keys() {
+        let filename = path.rsplit('/').next().unwrap();
+        for sdist_path in by_filename.get(filename).into_iter().flatten() {
+            if is_suffix(sdist_path, path) {
+                let prefix = &sdist_path[..sdist_path.len() - path.len()];
+                *prefixes
+                    .entry(top_level(path))
+                    .or_default()
+                    .entry(prefix)
+                    .or_default() += 1;
+            }
+        }
+    }
+    prefixes
+        .into_iter()
+        .map(|(top_level, counts)| {
+            // Ties go to the shortest prefix, so `a/b.py` doesn't map onto `tests/a/b.py`.
+            let (prefix, _) = counts
+                .into_iter()
+                .max_by(|(a, x), (b, y)| x.cmp(y).then_with(|| b.len().cmp(&a.len())))
+                .unwrap();
+            (top_level.to_string(), prefix.to_string())
+        })
+        .collect()
+}
+
+fn top_level(path: &str) -> &str {
+    match path.split_once('/') {
+        Some((dir, _)) => dir,
+        None => "",
+    }
+}
+
+// Whether `path` is `suffix`, or ends with it following a directory separator.
+fn is_suffix(path: &str, suffix: &str) -> bool {
+    path == suffix || (path.ends_with(suffix) && path[..path.len() - suffix.len()].ends_with('/'))
+}
+
+// Every Python file in a release tree, except the ones added by the import. Files a wheel
+// installs from `{name}.data/purelib` or `platlib` are mapped to where they end up.
+fn python_files(tree: &Tree) -> anyhow::Result<HashMap<String, Oid>> {
+    let mut files = HashMap::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if root.starts_with(".pypi-import/") || root.contains(".dist-info/") {
+            return TreeWalkResult::Skip;
+        }
+        let Some(name) = entry.name() else {
+            return TreeWalkResult::Ok;
+        };
+        if entry.kind() == Some(ObjectType::Blob) && name.ends_with(".py") {
+            let path = format!("{root}{name}");
+            let path = match path.split_once('/') {
+                Some((dir, rest)) if dir.ends_with(".data") => match rest.split_once('/') {
+                    Some(("purelib" | "platlib", rest)) => rest.to_string(),
+                    _ => return TreeWalkResult::Ok,
+                },
+                _ => path,
+            };
+            files.insert(path, entry.id());
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+// The archive paths of files that weren't imported, from the release manifest.
+fn skipped_files(repo: &Repository, tree: &Tree) -> Vec<String> {
+    let manifest = tree
+        .get_path(Path::new(RELEASE_MANIFEST_PATH))
+        .and_then(|entry| repo.find_blob(entry.id()));
+    let Ok(manifest) = manifest else {
+        return vec![];
+    };
+    match serde_json::from_slice::<ReleaseManifest>(manifest.content()) {
+        Ok(manifest) => manifest.skipped.into_iter().map(|s| s.path).collect(),
+        Err(e) => {
+            warn!("Invalid release manifest {}: {e}", manifest.id());
+            vec![]
+        }
+    }
+}
@@ -1,24 +1,79 @@
-use chrono::{DateTime, Utc};
+use crate::combine::JsonIndex;
+use crate::distribution::{DistributionFile, DistributionKind};
+use crate::partition::{
+    partition, write_partition, PartitionManifest, PartitionManifestEntry, PartitionOptions,
+    PartitionStrategy, PARTITION_MANIFEST_FILE,
+};
+use crate::policy::ExclusionPolicy;
+use crate::sampling::{stratified_sample, SamplingOptions, Stratify};
+use crate::simple_index::SimpleIndex;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use itertools::Itertools;
 use jwalk::{rayon, WalkDir};
+use log::{info, warn};
+use regex::Regex;
 use std::cmp::Ordering;
 
 use rayon::prelude::*;
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use std::fs;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use rand::seq::SliceRandom;
-use rand::thread_rng;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Url {
     url: String,
-    upload_time_iso_8601: DateTime<Utc>,
+    upload_time_iso_8601: Option<DateTime<Utc>>,
+    filename: Option<String>,
+    #[serde(flatten)]
+    metadata: FileMetadata,
+}
+
+/// The metadata PyPI publishes about each uploaded file, which is carried from the PyPI metadata
+/// through to the commit messages and the index.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct FileMetadata {
+    #[serde(alias = "packagetype", skip_serializing_if = "Option::is_none")]
+    pub package_type: Option<String>,
+    /// The Python tag of a wheel, or `source` for sdists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub python_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires_python: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yanked_reason: Option<String>,
+    #[serde(skip_serializing_if = "Digests::is_empty")]
+    pub digests: Digests,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct Digests {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake2b_256: Option<String>,
+}
+
+impl Digests {
+    pub fn is_empty(&self) -> bool {
+        self.md5.is_none() && self.sha256.is_none() && self.blake2b_256.is_none()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,9 +84,24 @@ struct PackageVersion {
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct DownloadJob {
     pub name: String,
+    /// The name normalised as described in PEP 503, which is what PyPI itself treats as the project.
+    #[serde(default)]
+    pub normalized_name: String,
     pub version: String,
     pub url: url::Url,
-    pub uploaded_on: DateTime<Utc>,
+    /// Missing for files from simple indexes that don't publish upload times.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploaded_on: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(flatten)]
+    pub metadata: FileMetadata,
+    /// Other projects that published this exact file, when duplicates are linked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// The other files uploaded for this release, when only one file per release is imported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<String>,
 }
 
 impl PartialOrd<Self> for DownloadJob {
@@ -40,45 +110,407 @@ impl PartialOrd<Self> for DownloadJob {
     }
 }
 
+// Jobs are ordered by upload time, with files without one first. Plenty of files share an upload
+// time, so the rest of the key just makes the order total, which seeded sampling depends on.
 impl Ord for DownloadJob {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.uploaded_on.cmp(&other.uploaded_on)
+        self.uploaded_on
+            .cmp(&other.uploaded_on)
+            .then_with(|| self.normalized_name.cmp(&other.normalized_name))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.package_filename().cmp(other.package_filename()))
     }
 }
 
 impl DownloadJob {
     pub fn package_filename(&self) -> &str {
-        self.url.path_segments().unwrap().last().unwrap()
+        match &self.filename {
+            Some(filename) => filename,
+            None => self.url.path_segments().unwrap().last().unwrap(),
+        }
+    }
+
+    /// The parsed filename, or `None` if it doesn't follow any of the usual conventions.
+    pub fn distribution(&self) -> Option<DistributionFile> {
+        DistributionFile::parse(self.package_filename(), &self.name)
+    }
+
+    /// The path this job's release is stored under in a combined repository, and in its index.
+    pub fn index_path(&self) -> String {
+        format!("{}/{}", self.name, self.package_filename())
+    }
+}
+
+/// Normalise a project name as described in PEP 503: lowercased, with runs of `-`, `_` and `.`
+/// collapsed into a single `-`.
+pub fn normalize_name(name: &str) -> String {
+    static SEPARATORS: OnceLock<Regex> = OnceLock::new();
+    SEPARATORS
+        .get_or_init(|| Regex::new(r"[-_.]+").unwrap())
+        .replace_all(name, "-")
+        .to_lowercase()
+}
+
+/// Options controlling which uploaded files end up in the partitions.
+#[derive(clap::Args, Debug)]
+pub struct SelectionOptions {
+    /// Skip releases already present in these index.json files, directories or state files
+    #[arg(long)]
+    pub since_index: Vec<PathBuf>,
+    /// TOML file of packages and files to exclude, instead of the built in policy
+    #[arg(long)]
+    pub policy: Option<PathBuf>,
+    /// What to do with files published under more than one project name
+    #[arg(long, value_enum, default_value_t)]
+    pub duplicates: DuplicatePolicy,
+    /// Which of the files uploaded for each release to import
+    #[arg(long, value_enum, default_value_t)]
+    pub select: ReleaseSelection,
+    /// Only include files uploaded on or after this date (or RFC 3339 time). Files without an
+    /// upload time are left out
+    #[arg(long, value_parser = parse_upload_time)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only include files uploaded before this date (or RFC 3339 time). Files without an upload
+    /// time are left out
+    #[arg(long, value_parser = parse_upload_time)]
+    pub until: Option<DateTime<Utc>>,
+    /// Only include files of these package types, e.g. sdist or bdist_wheel
+    #[arg(long)]
+    pub package_type: Vec<String>,
+    /// Only include files with these Python versions, e.g. source, py3 or cp311
+    #[arg(long)]
+    pub python_version: Vec<String>,
+    /// Only include packages whose name matches this regex
+    #[arg(long, value_parser = Regex::new)]
+    pub include_name: Option<Regex>,
+    /// Exclude packages whose name matches this regex
+    #[arg(long, value_parser = Regex::new)]
+    pub exclude_name: Option<Regex>,
+}
+
+impl SelectionOptions {
+    fn includes_package(&self, name: &str) -> bool {
+        self.include_name.as_ref().is_none_or(|r| r.is_match(name))
+            && !self.exclude_name.as_ref().is_some_and(|r| r.is_match(name))
+    }
+
+    fn includes_file(&self, job: &DownloadJob) -> bool {
+        let in_list = |values: &[String], value: &Option<String>| {
+            values.is_empty() || value.as_ref().is_some_and(|v| values.contains(v))
+        };
+        self.since
+            .is_none_or(|since| job.uploaded_on.is_some_and(|t| t >= since))
+            && self
+                .until
+                .is_none_or(|until| job.uploaded_on.is_some_and(|t| t < until))
+            && in_list(&self.package_type, &job.metadata.package_type)
+            && in_list(&self.python_version, &job.metadata.python_version)
+    }
+}
+
+fn parse_upload_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("expected a YYYY-MM-DD date or RFC 3339 time: {e}"))
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReleaseSelection {
+    /// Every file uploaded for a release
+    #[default]
+    All,
+    /// The sdist, otherwise a pure Python wheel, otherwise the newest wheel or upload
+    OnePerRelease,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum DuplicatePolicy {
+    /// Only keep the earliest upload of a file published under several project names
+    KeepFirst,
+    /// Keep every copy of the file, one under each project
+    #[default]
+    KeepAll,
+    /// Keep the earliest upload, and record the other projects as aliases of it
+    Link,
+}
+
+// Some projects have hidden duplicates. For example there is `fs.googledrivefs` and
+// `fs-googledrivefs`, which are distinct metadata files but the same project once normalised, and
+// which share some of their releases. A file is treated as a duplicate if another project has a
+// file with the same name and digest. Without a digest, only files from projects with the same
+// normalised name are considered duplicates.
+fn reconcile_duplicates(jobs: Vec<DownloadJob>, policy: DuplicatePolicy) -> Vec<DownloadJob> {
+    let groups = jobs.into_iter().sorted().into_group_map_by(|job| {
+        let digest = job
+            .metadata
+            .digests
+            .sha256
+            .clone()
+            .unwrap_or(job.normalized_name.clone());
+        (job.package_filename().to_string(), digest)
+    });
+
+    let mut duplicate_files = 0;
+    let mut projects = HashSet::new();
+    let mut reconciled = vec![];
+    for (_, mut group) in groups {
+        if group.len() > 1 {
+            duplicate_files += group.len() - 1;
+            projects.extend(group.iter().map(|job| job.name.clone()));
+        }
+        match policy {
+            DuplicatePolicy::KeepAll => reconciled.extend(group),
+            DuplicatePolicy::KeepFirst => reconciled.push(group.swap_remove(0)),
+            DuplicatePolicy::Link => {
+                let mut first = group.remove(0);
+                first.aliases = group.into_iter().map(|job| job.name).collect();
+                reconciled.push(first);
+            }
+        }
+    }
+    if duplicate_files != 0 {
+        info!(
+            "Found {duplicate_files} duplicate files across {} projects ({policy:?})",
+            projects.len()
+        );
+    }
+    reconciled
+}
+
+// A release usually has an sdist and a pile of platform wheels, which mostly contain the same
+// Python files. Importing all of them just multiplies the number of commits.
+fn select_release_files(jobs: Vec<DownloadJob>, selection: ReleaseSelection) -> Vec<DownloadJob> {
+    if let ReleaseSelection::All = selection {
+        return jobs;
+    }
+    let total = jobs.len();
+    let selected: Vec<_> = jobs
+        .into_iter()
+        .into_group_map_by(|job| (job.name.clone(), job.version.clone()))
+        .into_values()
+        .map(|mut files| {
+            files.sort();
+            let idx = files
+                .iter()
+                .position(|job| job.metadata.package_type.as_deref() == Some("sdist"))
+                .or_else(|| {
+                    files
+                        .iter()
+                        .position(|job| job.distribution().is_some_and(|d| d.is_pure_wheel()))
+                })
+                // Then the newest platform wheel, and only then whatever was uploaded last.
+                .or_else(|| {
+                    files.iter().rposition(|job| {
+                        job.distribution()
+                            .is_some_and(|d| d.kind == DistributionKind::Wheel)
+                    })
+                })
+                .unwrap_or(files.len() - 1);
+            let mut chosen = files.remove(idx);
+            chosen.siblings = files
+                .iter()
+                .map(|job| job.package_filename().to_string())
+                .collect();
+            chosen
+        })
+        .collect();
+    info!(
+        "Selected {} of {total} files, one per release",
+        selected.len()
+    );
+    selected
+}
+
+/// Written into the output directory of `create-urls`, listing the metadata that couldn't be used.
+pub const METADATA_ERRORS_FILE: &str = "errors.json";
+
+#[derive(Debug, Serialize)]
+pub struct MetadataError {
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub reason: String,
+}
+
+/// Read every file uploaded for the package described by a PyPI metadata file. A file that can't
+/// be read at all is a single error, otherwise only the uploads that are unusable are reported.
+pub fn read_package_metadata(path: &Path) -> (Vec<DownloadJob>, Vec<MetadataError>) {
+    let error = |version: Option<&str>, url: Option<&str>, reason: String| MetadataError {
+        path: path.to_path_buf(),
+        version: version.map(str::to_string),
+        url: url.map(str::to_string),
+        reason,
+    };
+    let package_name = match path.file_stem().and_then(|s| s.to_str()) {
+        Some(name) => name,
+        None => return (vec![], vec![error(None, None, "Invalid file name".into())]),
+    };
+    let versions = File::open(path).map_err(anyhow::Error::from).and_then(|f| {
+        serde_json::from_reader::<_, HashMap<String, PackageVersion>>(BufReader::new(f))
+            .map_err(anyhow::Error::from)
+    });
+    let versions = match versions {
+        Ok(versions) => versions,
+        Err(e) => return (vec![], vec![error(None, None, format!("{e:#}"))]),
+    };
+
+    let mut jobs = vec![];
+    let mut errors = vec![];
+    for (version, package_info) in versions {
+        let (release_jobs, release_errors) =
+            release_jobs(path, package_name, &version, package_info.urls);
+        jobs.extend(release_jobs);
+        errors.extend(release_errors);
+    }
+    (jobs, errors)
+}
+
+/// Parse the response of the PyPI JSON API for a single release, i.e. `/pypi/<name>/<version>/json`.
+pub fn parse_release_json(
+    source: &str,
+    name: &str,
+    version: &str,
+    body: &str,
+) -> anyhow::Result<(Vec<DownloadJob>, Vec<MetadataError>)> {
+    let release: PackageVersion = serde_json::from_str(body)?;
+    Ok(release_jobs(Path::new(source), name, version, release.urls))
+}
+
+fn release_jobs(
+    source: &Path,
+    name: &str,
+    version: &str,
+    urls: Vec<Url>,
+) -> (Vec<DownloadJob>, Vec<MetadataError>) {
+    let mut jobs = vec![];
+    let mut errors = vec![];
+    for url in urls {
+        let error = |reason: String| MetadataError {
+            path: source.to_path_buf(),
+            version: Some(version.to_string()),
+            url: Some(url.url.clone()),
+            reason,
+        };
+        let parsed_url = match url.url.parse::<url::Url>() {
+            Ok(parsed_url) => parsed_url,
+            Err(e) => {
+                errors.push(error(e.to_string()));
+                continue;
+            }
+        };
+        let Some(uploaded_on) = url.upload_time_iso_8601 else {
+            errors.push(error("Missing upload time".to_string()));
+            continue;
+        };
+        jobs.push(DownloadJob {
+            name: name.to_string(),
+            normalized_name: normalize_name(name),
+            version: version.to_string(),
+            url: parsed_url,
+            uploaded_on: Some(uploaded_on),
+            filename: url.filename,
+            metadata: url.metadata,
+            aliases: vec![],
+            siblings: vec![],
+        });
     }
+    (jobs, errors)
 }
 
-const EXCLUDE_PACKAGES: &[&str] = &[
-    // Just a bunch of python files containing base64 encoded contents
-    "pydwf",
-    // Gigantic, not even needed anymore. Same package as tensorflow.
-    "tensorflow-gpu",
-    "tensorflow-cpu",
-    // Nightly tensorflow packages account for a _lot_ of space
-    "tf-nightly",
-    "tf-nightly-cpu",
-    "tensorflow-io-nightly",
-    "tf-nightly-intel",
-    "tf-nightly-cpu-aws",
-    // Other misc nightly packages in the top 10
-    "pyagrum-nightly",
-];
+/// Collect the releases that have already been imported. Each path can be an `index.json` from a
+/// combined repository, a directory containing them (such as a checkout of every combined
+/// repository), or a state file listing one `name/filename` per line.
+pub fn load_imported_releases(paths: &[PathBuf]) -> anyhow::Result<HashSet<String>> {
+    let mut imported = HashSet::new();
+    for path in paths {
+        let files: Vec<PathBuf> = if path.is_dir() {
+            WalkDir::new(path)
+                .max_depth(2)
+                .into_iter()
+                .flatten()
+                .filter(|e| e.file_type().is_file() && e.file_name == "index.json")
+                .map(|e| e.path())
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+        for file in files {
+            let contents = fs::read_to_string(&file)
+                .with_context(|| format!("Error reading {}", file.display()))?;
+            if file.extension() == Some("json".as_ref()) {
+                let index: JsonIndex = serde_json::from_str(&contents)
+                    .with_context(|| format!("Error parsing {}", file.display()))?;
+                for entry in index.entries.into_values().flatten() {
+                    let filename = entry.path.file_name().unwrap().to_str().unwrap();
+                    for alias in &entry.aliases {
+                        imported.insert(format!("{alias}/{filename}"));
+                    }
+                    // Siblings were deliberately not imported, so don't pick them up next time.
+                    for sibling in &entry.siblings {
+                        imported.insert(format!("{}/{sibling}", entry.name));
+                    }
+                    imported.insert(entry.path.to_str().unwrap().to_string());
+                }
+            } else {
+                imported.extend(
+                    contents
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(str::to_string),
+                );
+            }
+        }
+    }
+    Ok(imported)
+}
 
-pub fn is_excluded_package(package_name: &str) -> bool {
-    EXCLUDE_PACKAGES.contains(&package_name)
+/// Where `create-urls` reads packages from: a tree of PyPI JSON metadata files, or the projects in
+/// a simple index.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum PackageSource {
+    Metadata(PathBuf),
+    Simple(String),
+}
+
+impl PackageSource {
+    fn name(&self) -> String {
+        match self {
+            PackageSource::Metadata(path) => path.file_stem().unwrap().to_string_lossy().into(),
+            PackageSource::Simple(name) => name.clone(),
+        }
+    }
+
+    // What `--find` matches against, which is always a metadata file name.
+    fn file_name(&self) -> String {
+        match self {
+            PackageSource::Metadata(path) => path.file_name().unwrap().to_string_lossy().into(),
+            PackageSource::Simple(name) => format!("{name}.json"),
+        }
+    }
 }
 
 pub fn extract_urls(
-    dir: PathBuf,
+    source: String,
     output_dir: PathBuf,
-    limit: Option<usize>,
     find: Option<Vec<String>>,
     split: usize,
-) {
+    options: SelectionOptions,
+    partition_options: PartitionOptions,
+    sampling: SamplingOptions,
+) -> anyhow::Result<()> {
+    let mut rng = sampling.rng();
+    let policy = ExclusionPolicy::load(options.policy.as_deref())?;
+
+    // Only releases that aren't in an existing corpus are emitted, so a refresh is proportional
+    // to the number of new uploads rather than the size of PyPI.
+    let already_imported = load_imported_releases(&options.since_index)?;
+
     let find = find.map(|v| {
         v.into_iter()
             .flat_map(|v| {
@@ -92,113 +524,131 @@ pub fn extract_urls(
             .collect::<Vec<_>>()
     });
 
-    let files_iter = WalkDir::new(dir)
-        .min_depth(2)
-        .into_iter()
-        .flatten()
-        .filter(|e| e.file_type().is_file());
+    let simple_index = SimpleIndex::open(&source)
+        .with_context(|| format!("Error opening simple index {source}"))?;
+    let sources_iter: Box<dyn Iterator<Item = PackageSource>> = match &simple_index {
+        Some(index) => Box::new(index.projects()?.into_iter().map(PackageSource::Simple)),
+        None => Box::new(
+            WalkDir::new(source)
+                .min_depth(2)
+                .into_iter()
+                .flatten()
+                .filter(|e| e.file_type().is_file())
+                .map(|e| PackageSource::Metadata(e.path())),
+        ),
+    };
+    let sources_iter = sources_iter.filter(|s| options.includes_package(&s.name()));
 
-    let mut files: Vec<_> = match find {
-        None => files_iter.collect(),
-        Some(matches) => files_iter
-            .filter(|e| matches.iter().any(|m| e.file_name.to_str().unwrap() == m))
+    let mut sources: Vec<_> = match find {
+        None => sources_iter.collect(),
+        Some(matches) => sources_iter
+            .filter(|s| matches.iter().any(|m| s.file_name() == *m))
             .collect(),
     };
 
-    let files = match limit {
-        None => files,
+    let sources = match sampling.limit {
+        None => sources,
         Some(_) => {
-            files.shuffle(&mut thread_rng());
-            files.into_iter().take(split).collect()
+            // The walk isn't in a stable order, which would make the seed pointless.
+            sources.sort();
+            sources.shuffle(&mut rng);
+            sources.into_iter().take(split).collect()
         }
     };
 
-    let iter = { files.into_par_iter() };
-
-    let mut all_urls: Vec<_> = iter
-        .flat_map(|entry| {
-            let reader = BufReader::new(File::open(entry.path()).unwrap());
-            let versions: HashMap<String, PackageVersion> =
-                serde_json::from_reader(reader).unwrap();
-            let entry_path = entry.path();
-            let package_name = entry_path.file_stem().unwrap().to_str().unwrap();
-            if is_excluded_package(package_name) {
-                return vec![];
-            }
-            versions
+    let packages: Vec<_> = sources
+        .into_par_iter()
+        .map(|source| {
+            let (jobs, errors) = match &source {
+                PackageSource::Metadata(path) => read_package_metadata(path),
+                PackageSource::Simple(name) => simple_index.as_ref().unwrap().project_jobs(name),
+            };
+            let jobs = jobs
                 .into_iter()
-                .flat_map(|(version, package_info)| {
-                    package_info.urls.into_iter().map(move |url| DownloadJob {
-                        name: package_name.to_string(),
-                        version: version.clone(),
-                        url: url.url.parse().unwrap(),
-                        uploaded_on: url.upload_time_iso_8601,
-                    })
-                })
-                .collect::<Vec<_>>()
+                .filter(|job| options.includes_file(job))
+                .collect();
+            (policy.evaluate(jobs), errors)
         })
         .collect();
-    all_urls.sort();
 
-    let chunks: Vec<Vec<_>> = all_urls
-        .into_iter()
-        .chunks(split)
+    let mut kept_jobs = vec![];
+    let mut errors = vec![];
+    let mut exclusions: HashMap<&str, (usize, u64)> = HashMap::new();
+    for ((kept, excluded), package_errors) in packages {
+        kept_jobs.extend(kept);
+        errors.extend(package_errors);
+        for (rule, job) in excluded {
+            let (files, bytes) = exclusions.entry(&rule.reason).or_default();
+            *files += 1;
+            *bytes += job.metadata.size.unwrap_or_default();
+        }
+    }
+    for (reason, (files, bytes)) in exclusions.into_iter().sorted() {
+        info!("Excluded {files} files ({bytes} bytes): {reason}");
+    }
+
+    let reconciled = reconcile_duplicates(kept_jobs, options.duplicates);
+    let mut all_urls: Vec<_> = select_release_files(reconciled, options.select)
         .into_iter()
-        .map(|v| v.collect())
+        .filter(|job| !already_imported.contains(&job.index_path()))
         .collect();
-    let chunks = match limit {
-        None => chunks,
-        Some(limit) => chunks.into_iter().rev().skip(1).take(limit).collect(),
+    all_urls.sort();
+    if let Some(limit) = sampling.limit {
+        all_urls = stratified_sample(all_urls, limit * split, sampling.stratify, &mut rng);
+        all_urls.sort();
+    }
+
+    let chunks = partition(all_urls, split, &partition_options);
+    let chunks = match (sampling.limit, sampling.stratify) {
+        (None, _) => chunks,
+        // The stratified sample is already the right size, and shouldn't lose its newest files.
+        (Some(_), Stratify::Year | Stratify::Size) => chunks,
+        // Hash partitions aren't in upload order, so there's no newest, probably incomplete, one
+        // to leave out.
+        (Some(limit), Stratify::None) => match partition_options.partition_by {
+            PartitionStrategy::Hash => chunks.into_iter().take(limit).collect(),
+            _ => chunks.into_iter().rev().skip(1).take(limit).collect(),
+        },
     };
 
-    for chunk in chunks {
-        let first_job_time = chunk.iter().map(|v| v.uploaded_on).min().unwrap();
-        let output_file_name = format!("{first_job_time}.json");
-        let output_file = File::create(output_dir.join(output_file_name)).unwrap();
-        let writer = BufWriter::new(output_file);
-        serde_json::to_writer(writer, &chunk).unwrap();
-    }
-}
-
-// pub fn extract_urls(
-//     dir: PathBuf,
-//     output_dir: PathBuf,
-//     limit: Option<usize>,
-//     find: Option<String>,
-//     split: usize,
-// ) {
-//     let files_iter: Vec<_> = WalkDir::new(dir)
-//         .min_depth(2)
-//         .into_iter()
-//         .flatten()
-//         .filter(|e| e.file_type().is_file()).collect();
-//
-//     let mut counts: HashMap<(i32, u32), usize> = HashMap::new();
-//
-//     for path in files_iter.into_iter().progress() {
-//         let reader = BufReader::new(File::open(path.path()).unwrap());
-//         let versions: HashMap<String, PackageVersion> = serde_json::from_reader(reader).unwrap();
-//
-//         for version in versions.into_values() {
-//             for url in version.urls {
-//                 let month = url.upload_time_iso_8601.month();
-//                 let year = url.upload_time_iso_8601.year();
-//                 match counts.entry((year, month)) {
-//                     Entry::Occupied(mut v) => {
-//                         v.insert(v.get() + 1);
-//                     }
-//                     Entry::Vacant(v) => {
-//                         v.insert(1);
-//                     }
-//                 }
-//             }
-//         }
-//     }
-//
-//     let mut total = 0;
-//
-//     for ((year, month), value) in counts.into_iter().sorted_by_cached_key(|(k, v)| k.clone()) {
-//         total += value;
-//         println!("{year}-{month} {value} (cum = {total})");
-//     }
-// }
+    let mut manifest = PartitionManifest {
+        strategy: partition_options.partition_by,
+        partitions: vec![],
+    };
+    for (name, chunk) in chunks {
+        let output_file_name = format!("{name}.jsonl");
+        let sha256 = write_partition(&output_dir.join(&output_file_name), &chunk)?;
+        let mut entry = PartitionManifestEntry::new(output_file_name, &chunk);
+        entry.sha256 = Some(sha256);
+        manifest.partitions.push(entry);
+    }
+    let manifest_file = File::create(output_dir.join(PARTITION_MANIFEST_FILE))?;
+    serde_json::to_writer_pretty(BufWriter::new(manifest_file), &manifest)?;
+
+    // Reported last, so that a problem with a handful of packages doesn't stop the rest.
+    if !errors.is_empty() {
+        warn!(
+            "{} metadata errors, see {METADATA_ERRORS_FILE}",
+            errors.len()
+        );
+    }
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    let errors_file = File::create(output_dir.join(METADATA_ERRORS_FILE))?;
+    serde_json::to_writer_pretty(BufWriter::new(errors_file), &errors)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pep_503_names() {
+        assert_eq!(normalize_name("Django"), "django");
+        assert_eq!(normalize_name("zope.interface"), "zope-interface");
+        assert_eq!(normalize_name("typing__extensions"), "typing-extensions");
+        assert_eq!(normalize_name("a-_.b"), "a-b");
+        assert_eq!(normalize_name("-foo-"), "-foo-");
+        assert_eq!(normalize_name("_foo."), "-foo-");
+    }
+}
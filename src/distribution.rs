@@ -0,0 +1,219 @@
+use crate::create_urls::normalize_name;
+use serde::{Deserialize, Serialize};
+
+/// The kinds of file that have been uploaded to PyPI over the years.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DistributionKind {
+    Wheel,
+    Sdist,
+    Egg,
+    Wininst,
+    Msi,
+}
+
+/// How a distribution file is packed. Wheels and eggs are zip files, and so (with an executable
+/// stub in front) are bdist_wininst installers.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    TarBz2,
+    TarXz,
+    Tar,
+    Exe,
+    Msi,
+}
+
+// Longest first, so `.tar.gz` wins over `.tar`.
+const EXTENSIONS: &[(&str, ArchiveFormat)] = &[
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tar.bz2", ArchiveFormat::TarBz2),
+    (".tar.xz", ArchiveFormat::TarXz),
+    (".tbz2", ArchiveFormat::TarBz2),
+    (".tgz", ArchiveFormat::TarGz),
+    (".txz", ArchiveFormat::TarXz),
+    (".tar", ArchiveFormat::Tar),
+    (".zip", ArchiveFormat::Zip),
+    (".whl", ArchiveFormat::Zip),
+    (".egg", ArchiveFormat::Zip),
+    (".exe", ArchiveFormat::Exe),
+    (".msi", ArchiveFormat::Msi),
+];
+
+impl ArchiveFormat {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        split_extension(filename).map(|(_, _, format)| format)
+    }
+}
+
+// Returns the filename without its extension, the (lowercased) extension and the format.
+fn split_extension(filename: &str) -> Option<(&str, &'static str, ArchiveFormat)> {
+    let lowercase = filename.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(extension, _)| lowercase.ends_with(extension))
+        .map(|(extension, format)| {
+            (
+                &filename[..filename.len() - extension.len()],
+                *extension,
+                *format,
+            )
+        })
+}
+
+/// Everything that can be worked out from the name of an uploaded file. Wheels follow PEP 427,
+/// eggs and installers the conventions of setuptools and distutils, and sdists are just
+/// `{name}-{version}` followed by an archive extension.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct DistributionFile {
+    pub kind: DistributionKind,
+    pub format: ArchiveFormat,
+    /// The name as written in the filename, which may be escaped or differ from the project name.
+    pub name: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// The Python tag of a wheel, or the Python version an egg or installer was built for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub python_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_tag: Option<String>,
+}
+
+impl DistributionFile {
+    /// Parse the filename of a file uploaded to `project`. The project name is needed to split
+    /// sdist and installer names, where both the name and the version can contain dashes.
+    pub fn parse(filename: &str, project: &str) -> Option<Self> {
+        let (stem, extension, format) = split_extension(filename)?;
+        let file = match extension {
+            ".whl" => {
+                let parts: Vec<_> = stem.split('-').collect();
+                let (name, version, build, tags) = match &parts[..] {
+                    [name, version, tags @ ..] if tags.len() == 3 => (name, version, None, tags),
+                    [name, version, build, tags @ ..] if tags.len() == 3 => {
+                        (name, version, Some(build.to_string()), tags)
+                    }
+                    _ => return None,
+                };
+                DistributionFile {
+                    kind: DistributionKind::Wheel,
+                    format,
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    build,
+                    python_tag: Some(tags[0].to_string()),
+                    abi_tag: Some(tags[1].to_string()),
+                    platform_tag: Some(tags[2].to_string()),
+                }
+            }
+            // `{name}-{version}-py{X.Y}-{platform}.egg`, where setuptools has already escaped any
+            // dashes in the name and version.
+            ".egg" => {
+                let mut parts = stem.splitn(3, '-');
+                let name = parts.next()?;
+                let version = parts.next()?;
+                let (python, platform) = match parts.next() {
+                    None => (None, None),
+                    Some(rest) => match rest.split_once('-') {
+                        Some((python, platform)) => (Some(python), Some(platform)),
+                        None => (Some(rest), None),
+                    },
+                };
+                DistributionFile {
+                    kind: DistributionKind::Egg,
+                    format,
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    build: None,
+                    python_tag: python.map(|p| p.trim_start_matches("py").to_string()),
+                    abi_tag: None,
+                    platform_tag: platform.map(str::to_string),
+                }
+            }
+            // `{name}-{version}.{platform}[-py{X.Y}].exe`, e.g. `foo-1.0.win-amd64-py2.7.exe`.
+            ".exe" | ".msi" => {
+                let (stem, python) = match stem.rsplit_once("-py") {
+                    Some((rest, python)) if is_python_version(python) => (rest, Some(python)),
+                    _ => (stem, None),
+                };
+                let (full_name, platform) = match stem.rfind(".win") {
+                    Some(idx) => (&stem[..idx], Some(&stem[idx + 1..])),
+                    None => (stem, None),
+                };
+                let (name, version) = split_name_version(full_name, project)?;
+                DistributionFile {
+                    kind: match format {
+                        ArchiveFormat::Msi => DistributionKind::Msi,
+                        _ => DistributionKind::Wininst,
+                    },
+                    format,
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    build: None,
+                    python_tag: python.map(str::to_string),
+                    abi_tag: None,
+                    platform_tag: platform.map(str::to_string),
+                }
+            }
+            _ => {
+                let (name, version) = split_name_version(stem, project)?;
+                DistributionFile {
+                    kind: DistributionKind::Sdist,
+                    format,
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    build: None,
+                    python_tag: None,
+                    abi_tag: None,
+                    platform_tag: None,
+                }
+            }
+        };
+        Some(file)
+    }
+
+    /// The `packagetype` PyPI reports for this kind of file.
+    pub fn package_type(&self) -> &'static str {
+        match self.kind {
+            DistributionKind::Wheel => "bdist_wheel",
+            DistributionKind::Sdist => "sdist",
+            DistributionKind::Egg => "bdist_egg",
+            DistributionKind::Wininst => "bdist_wininst",
+            DistributionKind::Msi => "bdist_msi",
+        }
+    }
+
+    /// The `python_version` PyPI reports for this file.
+    pub fn python_version(&self) -> Option<String> {
+        match self.kind {
+            DistributionKind::Sdist => Some("source".to_string()),
+            _ => self.python_tag.clone(),
+        }
+    }
+
+    /// A wheel that installs anywhere, i.e. one that only contains Python code.
+    pub fn is_pure_wheel(&self) -> bool {
+        self.kind == DistributionKind::Wheel
+            && self.abi_tag.as_deref() == Some("none")
+            && self.platform_tag.as_deref() == Some("any")
+    }
+}
+
+fn is_python_version(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+// Splits at the dash after the project name if there is one, and otherwise at the last dash.
+fn split_name_version<'a>(stem: &'a str, project: &str) -> Option<(&'a str, &'a str)> {
+    let project = normalize_name(project);
+    let (name, version) = stem
+        .match_indices('-')
+        .map(|(idx, _)| (&stem[..idx], &stem[idx + 1..]))
+        .find(|(name, _)| normalize_name(name) == project)
+        .or_else(|| stem.rsplit_once('-'))?;
+    (!name.is_empty() && !version.is_empty()).then_some((name, version))
+}
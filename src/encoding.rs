@@ -0,0 +1,255 @@
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_8};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncodingSource {
+    Bom,
+    Cookie,
+    Default,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SourceEncoding {
+    /// The encoding as Python would name it, e.g. `utf-8-sig` or `latin-1`.
+    pub name: String,
+    pub source: EncodingSource,
+    /// The contents couldn't be decoded using `name`, or we don't know how to decode it.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub undecodable: bool,
+    /// Where the UTF-8 copy of this file was written, if one was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utf8_copy: Option<String>,
+}
+
+impl SourceEncoding {
+    /// Plain UTF-8 files (with or without a `coding: utf-8` cookie) are the overwhelming
+    /// majority, and aren't worth recording.
+    pub fn is_default(&self) -> bool {
+        !self.undecodable
+            && self.source != EncodingSource::Bom
+            && codec_for_python_name(&self.name) == Some(Codec::Whatwg(UTF_8))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Codec {
+    Whatwg(&'static Encoding),
+    /// encoding_rs treats every latin-1 label as windows-1252, which decodes 0x80-0x9F to
+    /// different characters than Python does, so latin-1 is decoded by hand.
+    Latin1,
+}
+
+enum SourceDecoder {
+    Whatwg(Decoder),
+    Latin1,
+}
+
+/// Works out the encoding of a Python source file following PEP 263: a BOM wins, then a coding
+/// cookie in a comment on one of the first two lines, otherwise it's UTF-8. The file is then
+/// decoded as it streams past, to check it is valid and optionally to build a UTF-8 copy.
+pub struct EncodingScanner {
+    encoding: SourceEncoding,
+    decoder: Option<SourceDecoder>,
+    bom_remaining: usize,
+    scratch: String,
+    utf8_copy: Option<String>,
+}
+
+impl EncodingScanner {
+    /// `first` is the start of the file, which must be enough to contain the coding cookie.
+    pub fn new(first: &[u8], keep_utf8_copy: bool) -> Self {
+        let (name, source) = if let Some(name) = bom_encoding(first) {
+            (name.to_string(), EncodingSource::Bom)
+        } else if let Some(name) = coding_cookie(first) {
+            (name, EncodingSource::Cookie)
+        } else {
+            ("utf-8".to_string(), EncodingSource::Default)
+        };
+        let codec = codec_for_python_name(&name);
+        let encoding = SourceEncoding {
+            name,
+            source,
+            undecodable: codec.is_none(),
+            utf8_copy: None,
+        };
+        // A copy of a file that is already plain UTF-8 would be identical to the original.
+        let utf8_copy = (keep_utf8_copy && !encoding.is_default()).then(String::new);
+        EncodingScanner {
+            bom_remaining: first.len() - strip_bom(first).len(),
+            decoder: codec.map(|codec| match codec {
+                Codec::Whatwg(e) => SourceDecoder::Whatwg(e.new_decoder_without_bom_handling()),
+                Codec::Latin1 => SourceDecoder::Latin1,
+            }),
+            encoding,
+            scratch: String::new(),
+            utf8_copy,
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        let skip = self.bom_remaining.min(chunk.len());
+        self.bom_remaining -= skip;
+        self.decode(&chunk[skip..], false);
+    }
+
+    /// Returns the detected encoding, and the UTF-8 copy if one was asked for and it could be made.
+    pub fn finish(mut self) -> (SourceEncoding, Option<String>) {
+        self.decode(&[], true);
+        (self.encoding, self.utf8_copy)
+    }
+
+    fn decode(&mut self, mut chunk: &[u8], last: bool) {
+        let decoder = match &mut self.decoder {
+            Some(SourceDecoder::Whatwg(decoder)) => decoder,
+            Some(SourceDecoder::Latin1) => {
+                // Every byte is valid latin-1, and is the code point of the same value.
+                if let Some(copy) = &mut self.utf8_copy {
+                    copy.extend(chunk.iter().map(|&b| char::from(b)));
+                }
+                return;
+            }
+            None => return,
+        };
+        loop {
+            let output = match &mut self.utf8_copy {
+                Some(copy) => copy,
+                None => {
+                    self.scratch.clear();
+                    &mut self.scratch
+                }
+            };
+            output.reserve(
+                decoder
+                    .max_utf8_buffer_length_without_replacement(chunk.len())
+                    .unwrap_or(chunk.len() * 3),
+            );
+            let (result, read) = decoder.decode_to_string_without_replacement(chunk, output, last);
+            chunk = &chunk[read..];
+            match result {
+                DecoderResult::InputEmpty => return,
+                DecoderResult::OutputFull => continue,
+                DecoderResult::Malformed(_, _) => {
+                    self.encoding.undecodable = true;
+                    self.decoder = None;
+                    self.utf8_copy = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn bom_encoding(contents: &[u8]) -> Option<&'static str> {
+    // UTF-32 LE has to be checked before UTF-16 LE, as it shares a prefix.
+    if contents.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some("utf-8-sig")
+    } else if contents.starts_with(&[0xFF, 0xFE, 0x00, 0x00]) {
+        Some("utf-32-le")
+    } else if contents.starts_with(&[0x00, 0x00, 0xFE, 0xFF]) {
+        Some("utf-32-be")
+    } else if contents.starts_with(&[0xFF, 0xFE]) {
+        Some("utf-16-le")
+    } else if contents.starts_with(&[0xFE, 0xFF]) {
+        Some("utf-16-be")
+    } else {
+        None
+    }
+}
+
+fn strip_bom(contents: &[u8]) -> &[u8] {
+    match bom_encoding(contents) {
+        Some("utf-8-sig") => &contents[3..],
+        Some("utf-32-le" | "utf-32-be") => &contents[4..],
+        Some(_) => &contents[2..],
+        None => contents,
+    }
+}
+
+// The cookie regex from PEP 263 is `^[ \t\f]*#.*?coding[:=][ \t]*([-\w.]+)`, and it is only
+// looked for on the second line if the first line is also a comment or blank.
+fn coding_cookie(contents: &[u8]) -> Option<String> {
+    for line in contents.split(|c| *c == b'\n').take(2) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_start_matches([' ', '\t', '\x0c']);
+        if !line.starts_with('#') {
+            if line.trim().is_empty() {
+                continue;
+            }
+            return None;
+        }
+        let mut rest = line;
+        while let Some(idx) = rest.find("coding") {
+            rest = &rest[idx + "coding".len()..];
+            if let Some(value) = rest.strip_prefix([':', '=']) {
+                let name: String = value
+                    .trim_start_matches([' ', '\t'])
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                    .collect();
+                if !name.is_empty() {
+                    return Some(name.to_lowercase());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn codec_for_python_name(name: &str) -> Option<Codec> {
+    let name = name.to_lowercase().replace('_', "-");
+    // Python accepts a few spellings that the WHATWG labels used by encoding_rs don't.
+    let label = match name.as_str() {
+        "utf8" | "utf-8-sig" | "ascii" | "us-ascii" => "utf-8",
+        "latin-1" | "latin1" | "latin" | "l1" | "iso-8859-1" | "iso8859-1" | "8859" | "cp819"
+        | "iso-ir-100" | "csisolatin1" => return Some(Codec::Latin1),
+        "utf-16-le" | "utf-16le" => "utf-16le",
+        "utf-16-be" | "utf-16be" => "utf-16be",
+        "cp936" | "gb2312" => "gbk",
+        "cp932" | "sjis" | "shift-jis" => "shift_jis",
+        "euc-jp" | "eucjp" => "euc-jp",
+        "euc-kr" | "euckr" | "cp949" => "euc-kr",
+        "cp1250" | "cp1251" | "cp1252" | "cp1253" | "cp1254" | "cp1255" | "cp1256" | "cp1257"
+        | "cp1258" => {
+            return Encoding::for_label(name.replace("cp", "windows-").as_bytes())
+                .map(Codec::Whatwg)
+        }
+        other => other,
+    };
+    Encoding::for_label(label.as_bytes()).map(Codec::Whatwg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(contents: &[u8]) -> (SourceEncoding, Option<String>) {
+        let mut scanner = EncodingScanner::new(contents, true);
+        scanner.update(contents);
+        scanner.finish()
+    }
+
+    #[test]
+    fn latin1_c1_controls() {
+        let (encoding, copy) = scan(b"# -*- coding: latin-1 -*-\nx = '\x80\x9f\xe9'\n");
+        assert_eq!(encoding.source, EncodingSource::Cookie);
+        assert!(!encoding.undecodable);
+        assert_eq!(
+            copy.as_deref(),
+            Some("# -*- coding: latin-1 -*-\nx = '\u{80}\u{9f}\u{e9}'\n")
+        );
+    }
+
+    #[test]
+    fn cp1252_is_not_latin1() {
+        let (_, copy) = scan(b"# coding: cp1252\nx = '\x80'\n");
+        assert_eq!(copy.as_deref(), Some("# coding: cp1252\nx = '\u{20ac}'\n"));
+    }
+
+    #[test]
+    fn plain_utf8() {
+        let (encoding, copy) = scan("x = '\u{e9}'\n".as_bytes());
+        assert!(encoding.is_default());
+        assert_eq!(copy, None);
+    }
+}
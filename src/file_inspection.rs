@@ -1,35 +1,203 @@
+use crate::encoding::{EncodingScanner, SourceEncoding};
+use crate::obfuscation::{ObfuscationReport, ObfuscationScanner};
+use crate::release_metadata::RecordCheck;
+use crate::vendored::{
+    detect_vendored, load_known_blobs, KnownBlobs, VendoredAction, VendoredKind,
+};
 use anyhow::Result;
 use content_inspector::{inspect, ContentType};
-use git2::{ObjectType, Odb, Oid};
+use git2::{ObjectType, Odb, OdbWriter, Oid};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
 use std::io;
-use std::io::{BufRead, Read};
+use std::io::{Read, Write};
 
 const KB: u64 = 1024;
 const MB: u64 = 1024 * KB;
 const MAX_FILE_SIZE: u64 = 5 * MB;
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    Empty,
+    TooLarge,
+    NotPython,
+    Vendored(VendoredKind),
+    Binary,
+    Pyarmor,
+    GitLfs,
+    PulumiGenerated,
+    Obfuscated,
+    /// The archive declared a different size for the entry than it contained.
+    SizeMismatch,
+}
+
+impl SkipReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkipReason::Empty => "empty",
+            SkipReason::TooLarge => "too-large",
+            SkipReason::NotPython => "not-python",
+            SkipReason::Vendored(_) => "vendored",
+            SkipReason::Binary => "binary",
+            SkipReason::Pyarmor => "pyarmor",
+            SkipReason::GitLfs => "git-lfs",
+            SkipReason::PulumiGenerated => "pulumi-generated",
+            SkipReason::Obfuscated => "obfuscated",
+            SkipReason::SizeMismatch => "size-mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    pub reason: SkipReason,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct InspectionOptions {
+    /// Python files with an obfuscation score at or above this are not imported
+    #[arg(long, default_value = "0.6")]
+    pub obfuscation_threshold: f64,
+    /// Python files with an obfuscation score at or above this are tagged in the release manifest
+    #[arg(long, default_value = "0.4")]
+    pub obfuscation_tag_threshold: f64,
+    /// What to do with virtualenvs, build directories and vendored copies of other projects
+    #[arg(long, value_enum, default_value = "skip")]
+    pub vendored: VendoredAction,
+    /// File of blob IDs that are known to be vendored copies of other projects
+    #[arg(long, value_parser = load_known_blobs)]
+    pub vendored_blobs: Option<KnownBlobs>,
+    /// Also store a UTF-8 copy of any Python file that uses a BOM or a different encoding
+    #[arg(long)]
+    pub normalise_encoding: bool,
+    /// Read each file into memory and inspect it afterwards, as imports did before files were
+    /// streamed. Only bench-inspection sets this, to have something to compare against.
+    #[arg(skip)]
+    pub buffered: bool,
+}
+
+impl InspectionOptions {
+    /// Checks the options that clap can't check on its own.
+    pub fn validate(&self) -> Result<(), String> {
+        // Anything scoring above the tag threshold but not the skip threshold is tagged, so the
+        // other way round nothing would ever be tagged.
+        if self.obfuscation_tag_threshold > self.obfuscation_threshold {
+            return Err(format!(
+                "--obfuscation-tag-threshold ({}) can't be higher than --obfuscation-threshold ({})",
+                self.obfuscation_tag_threshold, self.obfuscation_threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Anything worth recording about a file that was imported.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FileAnnotations {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscation: Option<ObfuscationReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendored: Option<VendoredKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<SourceEncoding>,
+    /// Set when a wheel's RECORD has a different hash for this file, or doesn't list it at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<RecordCheck>,
+}
+
+impl FileAnnotations {
+    pub fn is_empty(&self) -> bool {
+        self.obfuscation.is_none()
+            && self.vendored.is_none()
+            && self.encoding.is_none()
+            && self.record.is_none()
+    }
+}
+
+pub struct WrittenFile {
+    pub oid: Oid,
+    pub sha256: String,
+    pub annotations: FileAnnotations,
+    pub utf8_copy: Option<Oid>,
+}
+
+pub enum Inspection {
+    Written(WrittenFile),
+    Skipped { reason: SkipReason, sha256: String },
+}
+
+/// Hash whatever is left in `reader`, prefixed by any bytes that have already been consumed from it.
+pub fn sha256_hex(consumed: &[u8], reader: &mut impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(consumed);
+    io::copy(reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// A single read might return less than the buffer size even when there is more to come, which
+// would make the checks on the start of the file unreliable.
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        match reader.read(&mut buffer[total..])? {
+            0 => break,
+            n => total += n,
+        }
+    }
+    Ok(total)
+}
+
+enum BlobDestination<'a> {
+    Stream(OdbWriter<'a>),
+    Buffer(Vec<u8>),
+}
+
 pub fn write_archive_entry_to_odb<R: Read>(
     path: &str,
     size: u64,
-    mut reader: &mut R,
+    reader: &mut R,
     odb: &Odb,
-) -> Result<Option<Oid>> {
+    options: &InspectionOptions,
+) -> Result<Inspection> {
     let mut first = [0; 1024];
-    let n = reader.read(&mut first[..])?;
+    let n = read_up_to(reader, &mut first)?;
     let first = &first[..n];
+
+    let skip = |reason: SkipReason, reader: &mut R| -> Result<Inspection> {
+        Ok(Inspection::Skipped {
+            reason,
+            sha256: sha256_hex(first, reader)?,
+        })
+    };
+
+    let mut annotations = FileAnnotations::default();
+    if options.vendored != VendoredAction::Import {
+        if let Some(kind) = detect_vendored(path) {
+            if options.vendored == VendoredAction::Skip {
+                return skip(SkipReason::Vendored(kind), reader);
+            }
+            annotations.vendored = Some(kind);
+        }
+    }
+
     let content_type = inspect(first);
     if content_type == ContentType::BINARY {
-        return Ok(None);
+        return skip(SkipReason::Binary, reader);
     }
     // Pyarmor files are just big bundles of bytecode. This isn't helpful and causes
     // large repositories. They appear to always start with this token.
     if first.starts_with("__pyarmor".as_ref()) {
-        return Ok(None);
+        return skip(SkipReason::Pyarmor, reader);
     }
     // Ignore git LFS files
     if first.starts_with("version https://git-lfs".as_ref()) {
-        return Ok(None);
+        return skip(SkipReason::GitLfs, reader);
     }
 
     // pulumi_azure_native contains some very big auto-generated files called _inputs.py
@@ -37,40 +205,185 @@ pub fn write_archive_entry_to_odb<R: Read>(
     if path.ends_with("_inputs.py") || path.ends_with("outputs.py") {
         let needle = b"WARNING: this file was generated by the Pulumi SDK Generator.";
         if first.windows(needle.len()).any(|window| window == needle) {
-            return Ok(None);
+            return skip(SkipReason::PulumiGenerated, reader);
         }
     }
 
-    let mut vec = Vec::with_capacity(size as usize);
-    vec.extend_from_slice(first);
-    io::copy(&mut reader, &mut vec)?;
+    // Everything else is worked out while the file streams into the object database, so it is
+    // only read once. The odb writer still holds the whole blob until it is finalized, but files
+    // rejected at the end are never finalized, and so are never written.
+    let mut destination = if options.buffered {
+        BlobDestination::Buffer(Vec::with_capacity(size as usize))
+    } else {
+        BlobDestination::Stream(odb.writer(size as usize, ObjectType::Blob)?)
+    };
+    let mut sha256 = Sha256::new();
+    let mut blob_hash = match (&options.vendored_blobs, options.vendored) {
+        (Some(_), VendoredAction::Skip | VendoredAction::Tag) => {
+            let mut hasher = Sha1::new();
+            hasher.update(format!("blob {size}\0"));
+            Some(hasher)
+        }
+        _ => None,
+    };
+    let mut obfuscation = ObfuscationScanner::default();
+    let mut encoding = EncodingScanner::new(first, options.normalise_encoding);
+
+    let mut scan = |chunk: &[u8]| {
+        sha256.update(chunk);
+        if let Some(hasher) = &mut blob_hash {
+            hasher.update(chunk);
+        }
+        obfuscation.update(chunk);
+        encoding.update(chunk);
+    };
+
+    let mut buffer = [0; 16 * KB as usize];
+    let mut chunk = first;
+    let mut consumed = 0;
+    loop {
+        consumed += chunk.len() as u64;
+        match &mut destination {
+            // The writer refuses anything past the size it was created with.
+            BlobDestination::Stream(writer) => {
+                if consumed <= size {
+                    writer.write_all(chunk)?;
+                }
+                scan(chunk);
+            }
+            BlobDestination::Buffer(contents) => contents.extend_from_slice(chunk),
+        }
+
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        chunk = &buffer[..n];
+    }
+    if let BlobDestination::Buffer(contents) = &destination {
+        scan(contents);
+    }
+
+    let sha256 = format!("{:x}", sha256.finalize());
+    let skipped = |reason| {
+        Ok(Inspection::Skipped {
+            reason,
+            sha256: sha256.clone(),
+        })
+    };
+
+    // A broken archive can declare the wrong size for an entry. The writer can only finalize a
+    // blob of the size it was given, so the file is recorded as skipped rather than failing the
+    // whole release.
+    if consumed != size {
+        return skipped(SkipReason::SizeMismatch);
+    }
+
+    let report = obfuscation.finish();
 
     // The areixio package contains very large python files that contain some kind of obfuscated
     // bytecode. We skip these, and potentially others in general, by detecting if the file have
     // very few lines but are comparatively large.
-    let num_lines = vec.lines().count();
-    if num_lines < 5 && size >= 50 * KB {
-        return Ok(None);
+    if report.line_count < 5 && size >= 50 * KB {
+        return skipped(SkipReason::Obfuscated);
     }
 
-    // let object_hash = Oid::hash_object(ObjectType::Blob, &vec).unwrap();
-    // Does the object exist already? Not thread safe, obviously.
-    // if odb.exists_ext(object_hash, OdbLookupFlags::NO_REFRESH) {
-    //     return Ok(None);
-    // }
-    Ok(Some(odb.write(ObjectType::Blob, &vec)?))
+    if report.score >= options.obfuscation_threshold {
+        return skipped(SkipReason::Obfuscated);
+    }
+    if report.score >= options.obfuscation_tag_threshold {
+        annotations.obfuscation = Some(report);
+    }
+
+    // Vendored copies that live outside a recognisable vendor directory (a top level `six.py`, say)
+    // can only be found by their content.
+    if let (Some(hasher), Some(known_blobs)) = (blob_hash, &options.vendored_blobs) {
+        let object_hash = Oid::from_bytes(&hasher.finalize())?;
+        if known_blobs.contains(&object_hash) {
+            if options.vendored == VendoredAction::Skip {
+                return skipped(SkipReason::Vendored(VendoredKind::VendoredPackage));
+            }
+            annotations.vendored = Some(VendoredKind::VendoredPackage);
+        }
+    }
+
+    let (encoding, utf8_text) = encoding.finish();
+    let utf8_copy = match utf8_text {
+        Some(text) => Some(odb.write(ObjectType::Blob, text.as_bytes())?),
+        None => None,
+    };
+    if !encoding.is_default() {
+        annotations.encoding = Some(encoding);
+    }
+
+    let oid = match destination {
+        BlobDestination::Stream(mut writer) => writer.finalize()?,
+        BlobDestination::Buffer(contents) => odb.write(ObjectType::Blob, &contents)?,
+    };
+    Ok(Inspection::Written(WrittenFile {
+        oid,
+        sha256,
+        annotations,
+        utf8_copy,
+    }))
 }
 
-pub fn skip_archive_entry(name: &str, size: u64) -> bool {
-    if !(1..=MAX_FILE_SIZE).contains(&size) {
-        return true;
+pub fn skip_archive_entry(name: &str, size: u64) -> Option<SkipReason> {
+    if size == 0 {
+        return Some(SkipReason::Empty);
+    }
+    if size > MAX_FILE_SIZE {
+        return Some(SkipReason::TooLarge);
     }
 
     if !name.ends_with(".py") {
-        return true;
+        return Some(SkipReason::NotPython);
     }
-    if name.contains("/venv/") || name.contains("/.venv/") {
-        return true;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Odb;
+
+    fn inspect_entry(contents: &[u8], size: u64) -> Inspection {
+        let odb = Odb::new().unwrap();
+        odb.add_new_mempack_backend(1).unwrap();
+        let options = InspectionOptions {
+            obfuscation_threshold: 0.6,
+            obfuscation_tag_threshold: 0.4,
+            vendored: VendoredAction::Skip,
+            vendored_blobs: None,
+            normalise_encoding: false,
+            buffered: false,
+        };
+        write_archive_entry_to_odb("pkg/mod.py", size, &mut &contents[..], &odb, &options).unwrap()
+    }
+
+    #[test]
+    fn declared_size() {
+        let contents = b"import os\n\nprint(os.getcwd())\n";
+        let Inspection::Written(file) = inspect_entry(contents, contents.len() as u64) else {
+            panic!("not written");
+        };
+        assert_eq!(
+            file.oid,
+            Oid::hash_object(ObjectType::Blob, contents).unwrap()
+        );
+    }
+
+    #[test]
+    fn wrong_declared_size() {
+        let contents = b"import os\n\nprint(os.getcwd())\n";
+        for size in [contents.len() as u64 - 5, contents.len() as u64 + 5] {
+            assert!(matches!(
+                inspect_entry(contents, size),
+                Inspection::Skipped {
+                    reason: SkipReason::SizeMismatch,
+                    ..
+                }
+            ));
+        }
     }
-    false
 }
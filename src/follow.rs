@@ -0,0 +1,427 @@
+use crate::create_urls::{parse_release_json, DownloadJob};
+use crate::file_inspection::InspectionOptions;
+use crate::job::{import_partition, APP_USER_AGENT};
+use crate::policy::ExclusionPolicy;
+use anyhow::{anyhow, bail, Context};
+use itertools::Itertools;
+use log::{info, warn};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+use ureq::Agent;
+
+#[derive(clap::Args, Debug)]
+pub struct FollowOptions {
+    /// Where the last processed serial and any pending jobs are kept between runs
+    #[arg()]
+    pub state_file: PathBuf,
+    #[arg()]
+    pub work_dir: PathBuf,
+    #[arg()]
+    pub finished_dir: PathBuf,
+    /// The XML-RPC endpoint, which must also serve the JSON API at <endpoint>/<name>/<version>/json
+    #[arg(long, default_value = "https://pypi.org/pypi")]
+    pub endpoint: String,
+    /// Seconds to wait between polls
+    #[arg(long, default_value = "60")]
+    pub interval: u64,
+    /// Import a partition once this many jobs are pending
+    #[arg(long, default_value = "1000")]
+    pub partition_size: usize,
+    /// Poll once, import whatever is pending and exit
+    #[arg(long)]
+    pub once: bool,
+    /// TOML file of packages and files to exclude, instead of the built in policy
+    #[arg(long)]
+    pub policy: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct FollowState {
+    /// The last changelog serial whose uploads have been turned into jobs.
+    serial: Option<i64>,
+    /// Jobs that haven't been imported into a partition yet.
+    pending: Vec<DownloadJob>,
+    /// The partition being imported, saved before the import starts so that a run that stops
+    /// part way through can finish it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    importing: Option<StartedPartition>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StartedPartition {
+    name: String,
+    jobs: Vec<DownloadJob>,
+}
+
+impl FollowState {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(FollowState::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid state {}", path.display()))
+    }
+
+    // Written to a temporary file first, so a crash never leaves a half written state behind.
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Tail the PyPI changelog, turning new file uploads into jobs and importing them in rolling
+/// partitions. The serial only advances once the jobs it produced are saved in the state file, and
+/// jobs only leave the state once the partition they went into is saved as well, so stopping at
+/// any point and starting again doesn't lose or repeat uploads.
+pub fn follow(options: FollowOptions, inspection: &InspectionOptions) -> anyhow::Result<()> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_read(Duration::from_secs(60))
+        .user_agent(APP_USER_AGENT)
+        .build();
+    let policy = ExclusionPolicy::load(options.policy.as_deref())?;
+    let mut state = FollowState::load(&options.state_file)?;
+    finish_partition(&mut state, &options, inspection)?;
+
+    loop {
+        match poll(&agent, &policy, &mut state, &options) {
+            Ok(()) => {}
+            Err(e) if options.once => return Err(e),
+            Err(e) => warn!("Error following the changelog, trying again later: {e:#}"),
+        }
+
+        if state.pending.len() >= options.partition_size
+            || (options.once && !state.pending.is_empty())
+        {
+            let mut jobs = std::mem::take(&mut state.pending);
+            jobs.sort();
+            // Pending jobs always come from a poll, which sets the serial. Serials only go up, so
+            // they name each partition uniquely.
+            let serial = state.serial.expect("pending jobs without a serial");
+            state.importing = Some(StartedPartition {
+                name: format!("serial-{serial}"),
+                jobs,
+            });
+            state.save(&options.state_file)?;
+            finish_partition(&mut state, &options, inspection)?;
+        }
+
+        if options.once {
+            return Ok(());
+        }
+        sleep(Duration::from_secs(options.interval));
+    }
+}
+
+// Turns everything uploaded since the last poll into pending jobs.
+fn poll(
+    agent: &Agent,
+    policy: &ExclusionPolicy,
+    state: &mut FollowState,
+    options: &FollowOptions,
+) -> anyhow::Result<()> {
+    let serial = match state.serial {
+        Some(serial) => serial,
+        // Starting from scratch would replay the whole of PyPI, so only follow from now on.
+        None => {
+            let serial = last_serial(agent, &options.endpoint)?;
+            info!("Starting from serial {serial}");
+            serial
+        }
+    };
+
+    let (jobs, new_serial) = new_uploads(agent, &options.endpoint, serial)?;
+    if !jobs.is_empty() {
+        info!("{} new files up to serial {new_serial}", jobs.len());
+    }
+    let jobs = jobs
+        .into_iter()
+        .into_group_map_by(|job| job.name.clone())
+        .into_values()
+        .flat_map(|jobs| policy.evaluate(jobs).0);
+    state.pending.extend(jobs);
+    state.serial = Some(new_serial);
+    state.save(&options.state_file)
+}
+
+// Imports the partition in `state.importing`, if there is one. A previous run may have stopped
+// anywhere in the middle of this, so a partition that has already been moved into place is just
+// marked as done, and one that was only part way through importing is started again.
+fn finish_partition(
+    state: &mut FollowState,
+    options: &FollowOptions,
+    inspection: &InspectionOptions,
+) -> anyhow::Result<()> {
+    // Only taken out of the state in memory. The saved state still has it until it's done.
+    let Some(StartedPartition { name, jobs }) = state.importing.take() else {
+        return Ok(());
+    };
+    if options.finished_dir.join(&name).exists() {
+        info!("Partition {name} was already imported");
+    } else {
+        let work_path = options.work_dir.join(&name);
+        if work_path.exists() {
+            warn!("Discarding unfinished import {}", work_path.display());
+            fs::remove_dir_all(&work_path)?;
+        }
+        let finished = import_partition(
+            &name,
+            jobs.into_iter().map(Ok),
+            &options.work_dir,
+            &options.finished_dir,
+            inspection,
+        )?;
+        if let Some(finished) = finished {
+            info!("Imported partition {}", finished.display());
+        }
+    }
+    state.save(&options.state_file)
+}
+
+const ATTEMPTS: u32 = 5;
+
+// PyPI has the odd bad moment, which shouldn't stop the follower.
+fn with_retries<T>(what: &str, mut call: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let mut delay = Duration::from_secs(1);
+    for _ in 1..ATTEMPTS {
+        match call() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                warn!("{what} failed, retrying in {}s: {e:#}", delay.as_secs());
+                sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+    call()
+}
+
+// Returns the jobs for every file added after `serial`, and the serial they go up to.
+fn new_uploads(
+    agent: &Agent,
+    endpoint: &str,
+    serial: i64,
+) -> anyhow::Result<(Vec<DownloadJob>, i64)> {
+    let changes = with_retries("changelog_since_serial", || {
+        xmlrpc_call(agent, endpoint, "changelog_since_serial", serial)
+    })?;
+    let Value::Array(changes) = changes else {
+        bail!("Unexpected changelog response {changes:?}");
+    };
+
+    let mut latest_serial = serial;
+    // Each release is only fetched once, however many files it had uploaded.
+    let mut added: HashMap<(String, String), Vec<String>> = HashMap::new();
+    for change in changes {
+        let Value::Array(fields) = change else {
+            bail!("Unexpected changelog entry {change:?}");
+        };
+        let [Value::Str(name), version, _, Value::Str(action), Value::Int(change_serial)] =
+            &fields[..]
+        else {
+            bail!("Unexpected changelog entry {fields:?}");
+        };
+        latest_serial = latest_serial.max(*change_serial);
+        // Uploads look like `add source file foo-1.0.tar.gz` or `add py3 file foo-1.0-py3-none-any.whl`.
+        let (Value::Str(version), Some(filename)) = (version, added_filename(action)) else {
+            continue;
+        };
+        added
+            .entry((name.clone(), version.clone()))
+            .or_default()
+            .push(filename.to_string());
+    }
+
+    let mut jobs = vec![];
+    for ((name, version), filenames) in added {
+        let url = format!("{endpoint}/{name}/{version}/json");
+        let body = with_retries(&url, || match agent.get(&url).call() {
+            Ok(response) => Ok(Some(response.into_string()?)),
+            // Deleted again before we got to it.
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        });
+        // Losing one release is better than never getting past it.
+        let body = match body {
+            Ok(Some(body)) => body,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Skipping {name} {version}: {e:#}");
+                continue;
+            }
+        };
+        let (release_jobs, errors) = match parse_release_json(&url, &name, &version, &body) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Skipping {name} {version}: {e:#}");
+                continue;
+            }
+        };
+        for error in errors {
+            warn!("{url}: {}", error.reason);
+        }
+        jobs.extend(
+            release_jobs
+                .into_iter()
+                .filter(|job| filenames.iter().any(|f| f == job.package_filename())),
+        );
+    }
+    Ok((jobs, latest_serial))
+}
+
+fn added_filename(action: &str) -> Option<&str> {
+    let rest = action.strip_prefix("add ")?;
+    let (_, filename) = rest.split_once(" file ")?;
+    Some(filename)
+}
+
+fn last_serial(agent: &Agent, endpoint: &str) -> anyhow::Result<i64> {
+    let response = with_retries("changelog_last_serial", || {
+        Ok(agent
+            .post(endpoint)
+            .set("Content-Type", "text/xml")
+            .send_string(&method_call("changelog_last_serial", None))?
+            .into_string()?)
+    })?;
+    match parse_response(&response)? {
+        Value::Int(serial) => Ok(serial),
+        other => Err(anyhow!("Unexpected serial {other:?}")),
+    }
+}
+
+fn xmlrpc_call(
+    agent: &Agent,
+    endpoint: &str,
+    method: &str,
+    argument: i64,
+) -> anyhow::Result<Value> {
+    let response = agent
+        .post(endpoint)
+        .set("Content-Type", "text/xml")
+        .send_string(&method_call(method, Some(argument)))
+        .with_context(|| format!("Error calling {method} on {endpoint}"))?
+        .into_string()?;
+    parse_response(&response)
+}
+
+fn method_call(method: &str, argument: Option<i64>) -> String {
+    let params = argument
+        .map(|a| format!("<param><value><int>{a}</int></value></param>"))
+        .unwrap_or_default();
+    format!(
+        "<?xml version=\"1.0\"?><methodCall><methodName>{method}</methodName><params>{params}</params></methodCall>"
+    )
+}
+
+// Just enough of XML-RPC to read the changelog.
+#[derive(Debug)]
+enum Value {
+    Int(i64),
+    Str(String),
+    Nil,
+    Array(Vec<Value>),
+    Struct(HashMap<String, Value>),
+}
+
+fn parse_response(body: &str) -> anyhow::Result<Value> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+    let mut fault = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"fault" => fault = true,
+            Event::Start(e) if e.name().as_ref() == b"value" => {
+                let value = parse_value(&mut reader)?;
+                if fault {
+                    let message = match &value {
+                        Value::Struct(members) => members.get("faultString"),
+                        _ => None,
+                    };
+                    bail!("XML-RPC fault: {message:?}");
+                }
+                return Ok(value);
+            }
+            Event::Eof => bail!("No value in XML-RPC response"),
+            _ => {}
+        }
+    }
+}
+
+// Parses the contents of a `<value>`, whose start tag has already been read, up to its end tag.
+fn parse_value(reader: &mut Reader<&[u8]>) -> anyhow::Result<Value> {
+    let value = match reader.read_event()? {
+        // A value without a type is a string.
+        Event::Text(text) => Value::Str(text.unescape()?.into_owned()),
+        Event::End(_) => return Ok(Value::Str(String::new())),
+        Event::Empty(e) if e.name().as_ref() == b"nil" => Value::Nil,
+        Event::Empty(e) if e.name().as_ref() == b"string" => Value::Str(String::new()),
+        Event::Start(e) => {
+            let tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+            match tag.as_str() {
+                "int" | "i4" | "i8" | "boolean" => Value::Int(read_text(reader)?.trim().parse()?),
+                "string" => Value::Str(read_text(reader)?),
+                "nil" => {
+                    read_text(reader)?;
+                    Value::Nil
+                }
+                "array" => Value::Array(parse_array(reader)?),
+                "struct" => Value::Struct(parse_struct(reader)?),
+                // Doubles, dates and base64, none of which the changelog uses.
+                _ => Value::Str(read_text(reader)?),
+            }
+        }
+        event => bail!("Unexpected XML-RPC value {event:?}"),
+    };
+    match reader.read_event()? {
+        Event::End(e) if e.name().as_ref() == b"value" => Ok(value),
+        event => bail!("Expected the end of a value, found {event:?}"),
+    }
+}
+
+fn parse_array(reader: &mut Reader<&[u8]>) -> anyhow::Result<Vec<Value>> {
+    let mut values = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"value" => values.push(parse_value(reader)?),
+            Event::End(e) if e.name().as_ref() == b"array" => return Ok(values),
+            Event::Start(_) | Event::End(_) | Event::Empty(_) => {}
+            event => bail!("Unexpected XML-RPC array contents {event:?}"),
+        }
+    }
+}
+
+fn parse_struct(reader: &mut Reader<&[u8]>) -> anyhow::Result<HashMap<String, Value>> {
+    let mut members = HashMap::new();
+    let mut name = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"name" => name = read_text(reader)?,
+            Event::Start(e) if e.name().as_ref() == b"value" => {
+                members.insert(std::mem::take(&mut name), parse_value(reader)?);
+            }
+            Event::End(e) if e.name().as_ref() == b"struct" => return Ok(members),
+            Event::Start(_) | Event::End(_) => {}
+            event => bail!("Unexpected XML-RPC struct contents {event:?}"),
+        }
+    }
+}
+
+// Reads the text up to the end of the current element.
+fn read_text(reader: &mut Reader<&[u8]>) -> anyhow::Result<String> {
+    let mut text = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Text(t) => text.push_str(&t.unescape()?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t.into_inner())),
+            Event::End(_) => return Ok(text),
+            event => bail!("Unexpected XML-RPC text {event:?}"),
+        }
+    }
+}
@@ -1,18 +1,26 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
-use crate::archive::{PackageArchive, PackageReader};
-use crate::create_urls::DownloadJob;
-
-use anyhow::Context;
-use git2::{Buf, Commit, FileMode, Index, Mempack, Odb, Oid, Repository, Signature, Time};
+use crate::archive::{ArchiveEntry, PackageArchive, PackageReader};
+use crate::create_urls::{DownloadJob, FileMetadata};
+use crate::distribution::{ArchiveFormat, DistributionFile};
+use crate::file_inspection::{FileAnnotations, InspectionOptions, SkipReason, SkippedFile};
+use crate::release_metadata::ReleaseMetadata;
+use crate::vendored::VendoredKind;
+
+use anyhow::{anyhow, bail, Context};
+use git2::{
+    Buf, Commit, FileMode, Index, Mempack, ObjectType, Odb, Oid, Repository, Signature, Time,
+};
 use itertools::Itertools;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
 
 use std::io::{Read, Write};
-use std::net::ToSocketAddrs;
-use std::path::PathBuf;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use std::{fs, io};
 
@@ -27,11 +35,172 @@ pub struct CommitMessage {
     pub version: String,
     pub file: String,
     pub path: PathBuf,
+    /// Other projects that published this exact file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<String>,
+    #[serde(flatten)]
+    pub metadata: FileMetadata,
+    /// What the filename says about the file, if it follows one of the usual conventions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distribution: Option<DistributionFile>,
+    #[serde(default, skip_serializing_if = "ReleaseMetadata::is_empty")]
+    pub release_metadata: ReleaseMetadata,
+    #[serde(default, skip_serializing_if = "FileStatus::is_available")]
+    pub status: FileStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
+}
+
+/// Whether a file can still be downloaded from PyPI. Removed files are committed with an empty
+/// tree, so they still appear in the index. Ordered by how far along a file is, as nothing that
+/// has been removed ever comes back.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileStatus {
+    #[default]
+    Available,
+    Yanked,
+    Removed,
+}
+
+impl FileStatus {
+    pub fn is_available(&self) -> bool {
+        *self == FileStatus::Available
+    }
+}
+
+/// Written into every release tree, describing the archive entries that were not imported.
+pub const RELEASE_MANIFEST_PATH: &str = ".pypi-import/manifest.json";
+/// UTF-8 copies of files in other encodings live under this prefix, mirroring their original path.
+pub const UTF8_COPY_PREFIX: &str = ".pypi-import/utf8/";
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct ReleaseManifest {
+    pub skipped: Vec<SkippedFile>,
+    pub files: BTreeMap<String, FileAnnotations>,
+}
+
+impl ReleaseManifest {
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty() && self.files.is_empty()
+    }
+}
+
+/// Written into the root of each partition repository once every job has been processed.
+pub const PARTITION_SUMMARY_FILE: &str = "summary.json";
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct PartitionSummary {
+    pub jobs: usize,
+    pub releases_committed: usize,
+    pub files_imported: usize,
+    pub files_skipped: BTreeMap<String, usize>,
+    pub vendored: BTreeMap<VendoredKind, usize>,
+    /// Imported files that have been yanked, which are still importable but worth knowing about.
+    pub yanked: usize,
+    /// Files that PyPI no longer serves.
+    pub removed: Vec<RemovedFile>,
+    /// Files that couldn't be downloaded after several attempts, and weren't committed.
+    pub download_failures: Vec<String>,
+    /// Imported wheel files that don't match the wheel's RECORD, or aren't listed in it.
+    pub record_mismatches: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RemovedFile {
+    pub name: String,
+    pub version: String,
+    pub filename: String,
+    pub http_status: u16,
+}
+
+impl PartitionSummary {
+    fn record(&mut self, release: &ExtractedRelease) {
+        if release.code.is_some() {
+            self.releases_committed += 1;
+        }
+        self.files_imported += release.file_count;
+        for skipped in &release.manifest.skipped {
+            *self
+                .files_skipped
+                .entry(skipped.reason.name().to_string())
+                .or_default() += 1;
+            if let SkipReason::Vendored(kind) = skipped.reason {
+                *self.vendored.entry(kind).or_default() += 1;
+            }
+        }
+        for annotations in release.manifest.files.values() {
+            if let Some(kind) = annotations.vendored {
+                *self.vendored.entry(kind).or_default() += 1;
+            }
+            if annotations.record.is_some() {
+                self.record_mismatches += 1;
+            }
+        }
+    }
+}
+
+enum Download {
+    Data(Vec<u8>),
+    /// PyPI responded with this status, meaning the file has been deleted.
+    Removed(u16),
+    Failed,
+}
+
+enum JobOutcome {
+    Extracted(Option<Box<ExtractedRelease>>),
+    Removed(u16),
+    Failed,
+}
+
+pub struct ExtractedRelease {
+    pub code: Option<(String, Oid)>,
+    pub file_count: usize,
+    pub manifest: ReleaseManifest,
+    pub release_metadata: ReleaseMetadata,
 }
 
 pub static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
-pub fn run_multiple(repo_path: &PathBuf, jobs: Vec<DownloadJob>) -> anyhow::Result<()> {
+/// Import a partition of jobs sorted by upload time into a repository called `name` in `work_dir`,
+/// which is moved into `finished_dir` once every job is done. Returns the finished repository, if
+/// there were any jobs.
+pub fn import_partition(
+    name: &str,
+    jobs: impl Iterator<Item = anyhow::Result<DownloadJob>> + Send,
+    work_dir: &Path,
+    finished_dir: &Path,
+    options: &InspectionOptions,
+) -> anyhow::Result<Option<PathBuf>> {
+    let repo_path = work_dir.join(name);
+    let finished_path = finished_dir.join(name);
+    // Checked up front, rather than finding out after importing everything.
+    if finished_path.exists() {
+        bail!("{} has already been imported", finished_path.display());
+    }
+
+    let mut jobs = jobs.peekable();
+    match jobs.peek() {
+        None => return Ok(None),
+        Some(Err(_)) => {
+            jobs.next().unwrap()?;
+        }
+        Some(Ok(_)) => {}
+    }
+
+    run_multiple(&repo_path, jobs, options)?;
+    fs::create_dir(&finished_path)?;
+    fs::rename(&repo_path, &finished_path)?;
+    Ok(Some(finished_path))
+}
+
+pub fn run_multiple(
+    repo_path: &PathBuf,
+    jobs: impl Iterator<Item = anyhow::Result<DownloadJob>> + Send,
+    options: &InspectionOptions,
+) -> anyhow::Result<()> {
     git2::opts::strict_object_creation(false);
     git2::opts::strict_hash_verification(false);
 
@@ -52,28 +221,53 @@ pub fn run_multiple(repo_path: &PathBuf, jobs: Vec<DownloadJob>) -> anyhow::Resu
 
     // I get quite a few DNS errors when using MacOS. I'm not sure why, but we could just avoid any
     // DNS overhead by re-using existing addresses? Fastly uses static anycast IPs, so why do we
-    // need to re-resolve them ever?
-    let dns_result: Result<Vec<_>, _> = "files.pythonhosted.org:443"
-        .to_socket_addrs()
-        .map(Iterator::collect);
-    let dns_result = dns_result?;
-
-    let agent = ureq::AgentBuilder::new()
-        .https_only(true)
-        .timeout_read(Duration::from_secs(30))
-        .user_agent(APP_USER_AGENT)
-        .resolver(move |addr: &str| match addr {
-            "files.pythonhosted.org:443" => Ok(dns_result.clone()),
-            _ => panic!("Unexpected address {addr}"),
-        })
-        .build();
-
-    fn download_with_retry(agent: &mut Agent, url: &Url) -> anyhow::Result<Option<Vec<u8>>> {
+    // need to re-resolve them ever? They are only looked up once something is downloaded from
+    // there, so imports from a local index don't need DNS at all.
+    let pypi_addrs: Arc<OnceLock<Vec<SocketAddr>>> = Arc::default();
+
+    // Files from other indexes (such as a local proxy, which may well be plain HTTP) are resolved
+    // as normal.
+    let resolver = move |addr: &str| match addr {
+        "files.pythonhosted.org:443" => {
+            if let Some(addrs) = pypi_addrs.get() {
+                return Ok(addrs.clone());
+            }
+            let addrs = addr.to_socket_addrs()?.collect();
+            Ok(pypi_addrs.get_or_init(|| addrs).clone())
+        }
+        _ => addr.to_socket_addrs().map(Iterator::collect),
+    };
+    let build_agent = |https_only| {
+        ureq::AgentBuilder::new()
+            .https_only(https_only)
+            .timeout_read(Duration::from_secs(30))
+            .user_agent(APP_USER_AGENT)
+            .resolver(resolver.clone())
+            .build()
+    };
+    // Only jobs with a plain HTTP URL, from an index that is itself served over HTTP, may be
+    // downloaded over it. Everything else can't be redirected to plain HTTP.
+    let https_agent = build_agent(true);
+    let http_agent = build_agent(false);
+
+    fn download_with_retry(agent: &mut Agent, url: &Url) -> anyhow::Result<Download> {
+        // Static simple indexes link straight to files on disk.
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file URL {url}"))?;
+            return match fs::read(&path) {
+                Ok(data) => Ok(Download::Data(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Download::Removed(404)),
+                Err(e) => Err(e).with_context(|| format!("Error reading {}", path.display())),
+            };
+        }
         for _i in 0..5 {
             let response = match agent.get(url.as_str()).call() {
                 Ok(response) => Ok::<_, anyhow::Error>(response),
-                Err(ureq::Error::Status(404, _)) => return Ok(None),
-                Err(ureq::Error::Status(416, _)) => return Ok(None),
+                Err(ureq::Error::Status(status @ (404 | 410 | 416), _)) => {
+                    return Ok(Download::Removed(status))
+                }
                 Err(e) => Err(e.into()),
             }
             .with_context(|| format!("Error fetching URL {}", url))?;
@@ -86,7 +280,7 @@ pub fn run_multiple(repo_path: &PathBuf, jobs: Vec<DownloadJob>) -> anyhow::Resu
             };
 
             match response.into_reader().read_to_end(&mut data) {
-                Ok(_) => return Ok(Some(data)),
+                Ok(_) => return Ok(Download::Data(data)),
                 Err(e) => {
                     warn!("{url} failed: {e}");
                     continue;
@@ -94,27 +288,33 @@ pub fn run_multiple(repo_path: &PathBuf, jobs: Vec<DownloadJob>) -> anyhow::Resu
             }
         }
         warn!("Skipping {url} due to 5 errors");
-        Ok(None)
+        Ok(Download::Failed)
     }
 
-    let extracted_packages = jobs
-        .into_par_iter()
+    let mut extracted_packages = jobs
+        .par_bridge()
         .map_init(
             || {
-                let agent = agent.clone();
+                let agents = (https_agent.clone(), http_agent.clone());
                 let output_repo = Repository::open(repo_path).unwrap();
                 output_repo.set_odb(&odb).unwrap();
-                (agent, output_repo)
+                (agents, output_repo)
             },
-            |(agent, repo), job| {
+            |((https_agent, http_agent), repo), job| {
+                let job = job?;
+                let agent = match job.url.scheme() {
+                    "http" => http_agent,
+                    _ => https_agent,
+                };
                 let data = match download_with_retry(agent, &job.url)? {
-                    None => return Ok((job, None)),
-                    Some(d) => d,
+                    Download::Data(d) => d,
+                    Download::Removed(status) => return Ok((job, JobOutcome::Removed(status))),
+                    Download::Failed => return Ok((job, JobOutcome::Failed)),
                 };
                 let reader = io::Cursor::new(data);
 
-                let item =
-                    extract(&job, &odb, reader, repo, &baseline_tree_oid).with_context(|| {
+                let item = extract(&job, &odb, reader, repo, &baseline_tree_oid, options)
+                    .with_context(|| {
                         format!(
                             "Error processing {} / {} / {}",
                             job.name,
@@ -122,19 +322,74 @@ pub fn run_multiple(repo_path: &PathBuf, jobs: Vec<DownloadJob>) -> anyhow::Resu
                             job.package_filename()
                         )
                     })?;
-                Ok::<_, anyhow::Error>((job, item))
+                Ok::<_, anyhow::Error>((job, JobOutcome::Extracted(item.map(Box::new))))
             },
         )
         .collect::<Result<Vec<_>, _>>()?;
+    // Jobs are streamed in, so they finish in any order. Commit them in upload order.
+    extracted_packages.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-    for (job, result) in extracted_packages {
-        if let Some((path, tree_oid)) = result {
-            commit(&repo, &job, path, tree_oid);
+    let mut summary = PartitionSummary {
+        jobs: extracted_packages.len(),
+        ..Default::default()
+    };
+
+    for (job, outcome) in extracted_packages {
+        match outcome {
+            JobOutcome::Extracted(Some(release)) => {
+                summary.record(&release);
+                if let Some((path, tree_oid)) = release.code {
+                    let release_metadata = release.release_metadata;
+                    let (status, reason) = match job.metadata.yanked {
+                        true => (FileStatus::Yanked, job.metadata.yanked_reason.clone()),
+                        false => (FileStatus::Available, None),
+                    };
+                    if job.metadata.yanked {
+                        summary.yanked += 1;
+                    }
+                    commit(
+                        &repo,
+                        &job,
+                        path,
+                        tree_oid,
+                        release_metadata,
+                        status,
+                        reason,
+                    );
+                }
+            }
+            JobOutcome::Extracted(None) => {}
+            // Committed with an empty tree, so the index still knows the file existed.
+            JobOutcome::Removed(http_status) => {
+                let reason = format!("HTTP {http_status}");
+                let path = package_path(&job);
+                commit(
+                    &repo,
+                    &job,
+                    path,
+                    baseline_tree_oid,
+                    ReleaseMetadata::default(),
+                    FileStatus::Removed,
+                    Some(reason),
+                );
+                summary.removed.push(RemovedFile {
+                    name: job.name.clone(),
+                    version: job.version.clone(),
+                    filename: job.package_filename().to_string(),
+                    http_status,
+                });
+            }
+            JobOutcome::Failed => summary.download_failures.push(job.url.to_string()),
         }
     }
 
     let repo_index = repo.index().unwrap();
     flush_repo(&repo, repo_index, &odb, mempack_backend);
+
+    fs::write(
+        repo_path.join(PARTITION_SUMMARY_FILE),
+        serde_json::to_string_pretty(&summary)?,
+    )?;
     Ok(())
 }
 
@@ -144,10 +399,12 @@ pub fn extract(
     reader: PackageReader,
     repo: &mut Repository,
     baseline_tree_oid: &Oid,
-) -> anyhow::Result<Option<(String, Oid)>> {
-    let package_filename = job.package_filename();
-    let package_extension = package_filename.rsplit('.').next().unwrap();
-    let mut archive = match PackageArchive::new(package_extension, reader) {
+    options: &InspectionOptions,
+) -> anyhow::Result<Option<ExtractedRelease>> {
+    let Some(format) = ArchiveFormat::from_filename(job.package_filename()) else {
+        return Ok(None);
+    };
+    let mut archive = match PackageArchive::new(format, reader) {
         None => {
             return Ok(None);
         }
@@ -156,10 +413,22 @@ pub fn extract(
 
     let mut file_count = 0;
 
+    let mut manifest = ReleaseManifest::default();
+    let mut metadata_files = vec![];
+
     let all_items: Vec<_> = archive
-        .all_items(odb)
+        .all_items(odb, options)
         .flat_map(|v| match v {
-            Ok(v) => Some(v),
+            Ok(ArchiveEntry::Imported(name, file)) => Some((name, file)),
+            Ok(ArchiveEntry::Skipped(skipped)) => {
+                manifest.skipped.push(skipped);
+                None
+            }
+            Ok(ArchiveEntry::Metadata(skipped, contents)) => {
+                metadata_files.push((skipped.path.clone(), contents));
+                manifest.skipped.push(skipped);
+                None
+            }
             Err(e) => {
                 error!("Error with package {}: {e}", job.url);
                 None
@@ -177,12 +446,12 @@ pub fn extract(
         })
         .collect();
 
-    // Some packages have hidden "duplicate" packages. For example there is `fs.googledrivefs` and `fs-googledrivefs`.
-    // These are distinct *packages*, but `fs-googledrivefs` has releases that are also under `fs.googledrivefs`.
-    // I've verified that there are currently no two files with the same name but different hashes, which is a relief,
-    // but the fact two packages have the same name causes issues with the "strip first component" check.
-    // Instead of checking for a specific string, we can instead check if there is a shared common prefix with
-    // all files. If there is only one shared common prefix then we strip it.
+    // Some packages have hidden "duplicate" packages, like `fs.googledrivefs` and `fs-googledrivefs`,
+    // whose shared releases are reconciled by `create-urls`. Partitions created with
+    // `--duplicates keep-all` (or before that existed) can still contain the same file under both
+    // names though, which breaks checking for a specific package name in the "strip first component"
+    // check. Instead we check if there is a shared common prefix with all files. If there is only
+    // one shared common prefix then we strip it.
     let first_segment_to_skip = if all_items.len() > 1 {
         let first_segments: Vec<_> = all_items
             .iter()
@@ -199,9 +468,19 @@ pub fn extract(
         None
     };
 
+    // setup.py is imported like any other Python file, so it is read back rather than kept.
+    let setup_py = all_items
+        .iter()
+        .find(|(path, _)| matches!(path.split_once('/'), Some((_, "setup.py"))))
+        .map(|(_, file)| odb.read(file.oid))
+        .transpose()?;
+    let setup_py = setup_py.as_ref().map(|o| String::from_utf8_lossy(o.data()));
+    let (release_metadata, record) =
+        ReleaseMetadata::from_files(&metadata_files, setup_py.as_deref());
+
     let mut tree_builder = TreeUpdateBuilder::new();
 
-    for (original_file_name, oid) in &all_items {
+    for (original_file_name, file) in &all_items {
         let file_name = match first_segment_to_skip {
             None => original_file_name,
             Some(to_strip) => {
@@ -224,20 +503,49 @@ pub fn extract(
             .replace("/.git/", "/dot-git/");
         let path_without_slash = path.trim_start_matches('/');
 
-        tree_builder.upsert(path_without_slash, *oid, FileMode::Blob);
+        tree_builder.upsert(path_without_slash, file.oid, FileMode::Blob);
         file_count += 1;
+
+        let mut annotations = file.annotations.clone();
+        // RECORD paths are relative to the root of the wheel, which is never stripped.
+        if let Some(record) = &record {
+            annotations.record = record.check(original_file_name, &file.sha256);
+            if annotations.record.is_some() {
+                warn!("{} doesn't match RECORD in {}", original_file_name, job.url);
+            }
+        }
+        if let (Some(utf8_oid), Some(encoding)) = (file.utf8_copy, &mut annotations.encoding) {
+            let utf8_path = format!("{UTF8_COPY_PREFIX}{path_without_slash}");
+            tree_builder.upsert(&utf8_path, utf8_oid, FileMode::Blob);
+            encoding.utf8_copy = Some(utf8_path);
+        }
+        if !annotations.is_empty() {
+            manifest
+                .files
+                .insert(path_without_slash.to_string(), annotations);
+        }
+    }
+
+    // Releases whose entries were all skipped are still committed with just the manifest, which is
+    // the only record of what they contained (e.g. nothing but a `.so`).
+    if !manifest.is_empty() {
+        let manifest_oid = odb.write(ObjectType::Blob, &serde_json::to_vec(&manifest)?)?;
+        tree_builder.upsert(RELEASE_MANIFEST_PATH, manifest_oid, FileMode::Blob);
     }
 
     let baseline_tree = repo.find_tree(*baseline_tree_oid)?;
     let tree_oid = tree_builder.create_updated(repo, &baseline_tree)?;
 
-    let package_prefix = format!("packages/{}/{package_filename}", job.name);
+    Ok(Some(ExtractedRelease {
+        code: (file_count != 0 || !manifest.is_empty()).then_some((package_path(job), tree_oid)),
+        file_count,
+        manifest,
+        release_metadata,
+    }))
+}
 
-    if file_count == 0 {
-        Ok(None)
-    } else {
-        Ok(Some((package_prefix, tree_oid)))
-    }
+fn package_path(job: &DownloadJob) -> String {
+    format!("packages/{}/{}", job.name, job.package_filename())
 }
 
 pub fn commit<'a>(
@@ -245,12 +553,17 @@ pub fn commit<'a>(
     info: &DownloadJob,
     code_path: String,
     tree_oid: Oid,
+    release_metadata: ReleaseMetadata,
+    status: FileStatus,
+    status_reason: Option<String>,
 ) -> Commit<'a> {
     let filename = info.package_filename();
     let signature = Signature::new(
         "Tom Forbes",
         "tom@tomforb.es",
-        &Time::new(info.uploaded_on.timestamp(), 0),
+        // Files without an upload time are committed at the epoch, which the index treats as
+        // unknown.
+        &Time::new(info.uploaded_on.map_or(0, |t| t.timestamp()), 0),
     )
     .unwrap();
     let commit_message = serde_json::to_string(&CommitMessage {
@@ -258,6 +571,13 @@ pub fn commit<'a>(
         version: info.version.clone(),
         file: filename.to_string(),
         path: code_path.into(),
+        aliases: info.aliases.clone(),
+        metadata: info.metadata.clone(),
+        distribution: info.distribution(),
+        release_metadata,
+        status,
+        status_reason,
+        siblings: info.siblings.clone(),
     })
     .unwrap();
     let tree = repo.find_tree(tree_oid).unwrap();
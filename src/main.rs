@@ -1,25 +1,41 @@
 extern crate core;
 
 mod archive;
+mod bench;
 mod combine;
+mod consistency;
 mod create_urls;
+mod distribution;
+mod encoding;
 mod file_inspection;
+mod follow;
 mod gitub;
 mod inspect;
 mod job;
+mod obfuscation;
+mod partition;
+mod policy;
+mod release_metadata;
+mod sampling;
 mod scanner;
+mod setup_py;
+mod simple_index;
+mod stats;
 mod utils;
+mod vendored;
+mod version;
 
-use std::fs;
-use std::fs::File;
-use std::io::BufReader;
+use clap::{CommandFactory, Parser};
 
-use clap::Parser;
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
 
-use anyhow::Context;
-use std::path::PathBuf;
-
-use crate::create_urls::DownloadJob;
+use crate::create_urls::SelectionOptions;
+use crate::file_inspection::InspectionOptions;
+use crate::follow::FollowOptions;
+use crate::partition::{read_partition, PartitionOptions};
+use crate::sampling::SamplingOptions;
+use crate::stats::StatsFormat;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -28,9 +44,12 @@ struct Cli {
     run_type: RunType,
 }
 
+// Only one of these is ever constructed, so there's no point boxing the large variants.
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand)]
 enum RunType {
     FromJson {
+        /// A partition file from create-urls, or - to read jobs from stdin
         #[arg()]
         input_file: PathBuf,
         #[arg()]
@@ -39,24 +58,48 @@ enum RunType {
         finished_dir: PathBuf,
         #[arg()]
         template: PathBuf,
+        /// Name of the partition repository, by default the input file's name without its extension
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        inspection: InspectionOptions,
     },
     CreateUrls {
+        /// A directory of PyPI JSON metadata, or a simple index URL or directory
         #[arg()]
-        data: PathBuf,
+        data: String,
         #[arg()]
         output_dir: PathBuf,
         #[arg(long, short)]
-        limit: Option<usize>,
-        #[arg(long, short)]
         find: Option<Vec<String>>,
         #[arg(long, short, default_value = "5000")]
         split: usize,
+        #[command(flatten)]
+        selection: SelectionOptions,
+        #[command(flatten)]
+        partitioning: PartitionOptions,
+        #[command(flatten)]
+        sampling: SamplingOptions,
     },
     MergeBranches {
         #[arg()]
         into: PathBuf,
         #[arg()]
         repos: Vec<PathBuf>,
+        /// removed.json files from earlier builds of the combined repository, whose marks are kept
+        #[arg(long)]
+        removed: Vec<PathBuf>,
+    },
+    /// Mark files in a combined repository's index as removed from PyPI. The marks are also listed
+    /// in removed.json, which has to be passed to merge-branches --removed when rebuilding it
+    MarkRemoved {
+        #[arg()]
+        repo: PathBuf,
+        /// Files to mark, as name/filename like they appear in index.json
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(long, default_value = "Removed from PyPI")]
+        reason: String,
     },
     CreateRepository {
         #[arg()]
@@ -68,57 +111,138 @@ enum RunType {
         #[arg()]
         cmd: String,
     },
+    Stats {
+        #[arg()]
+        data: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        format: StatsFormat,
+        /// How many of the largest packages to list
+        #[arg(long, default_value = "25")]
+        top: usize,
+        #[arg(long, short, default_value = "5000")]
+        split: usize,
+        #[command(flatten)]
+        partitioning: PartitionOptions,
+    },
+    /// Report Python files in wheels that are missing from, or differ to, the release's sdist
+    Consistency {
+        /// A partition repository or a combined repository
+        #[arg()]
+        repo: PathBuf,
+    },
+    /// Keep importing new uploads as they appear in the PyPI changelog
+    Follow {
+        #[command(flatten)]
+        follow: FollowOptions,
+        #[command(flatten)]
+        inspection: InspectionOptions,
+    },
+    BenchInspection {
+        #[arg()]
+        corpus: PathBuf,
+        #[arg(long, short, default_value = "1")]
+        iterations: usize,
+        #[command(flatten)]
+        inspection: InspectionOptions,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     let args: Cli = Cli::parse();
     env_logger::init();
 
+    if let RunType::FromJson { inspection, .. }
+    | RunType::Follow { inspection, .. }
+    | RunType::BenchInspection { inspection, .. } = &args.run_type
+    {
+        if let Err(e) = inspection.validate() {
+            Cli::command()
+                .error(clap::error::ErrorKind::ArgumentConflict, e)
+                .exit();
+        }
+    }
+
     match args.run_type {
         RunType::FromJson {
             input_file,
             work_dir,
             finished_dir,
             template: _,
+            name,
+            inspection,
         } => {
-            let reader = BufReader::new(File::open(&input_file).unwrap());
-            let input: Vec<DownloadJob> = serde_json::from_reader(reader).unwrap();
-
-            let first_job_time = input.iter().map(|v| v.uploaded_on).min().unwrap();
-            let repo_path = work_dir.join(format!("{first_job_time}"));
-            let finished_path = finished_dir.join(format!("{first_job_time}"));
+            let name = match name {
+                Some(name) => name,
+                None if input_file == Path::new("-") => {
+                    bail!("--name is required when reading jobs from stdin")
+                }
+                None => input_file
+                    .file_stem()
+                    .context("Invalid input file")?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let input = read_partition(&input_file).unwrap();
 
             // let opts = CopyOptions::new();
             // fs::create_dir(&repo_path).unwrap();
             // fs_extra::dir::copy(template.join(".git/"), &repo_path, &opts).unwrap();
             // let repo_path = fs::canonicalize(&repo_path).unwrap();
 
-            job::run_multiple(&repo_path, input)
+            job::import_partition(&name, input, &work_dir, &finished_dir, &inspection)
                 .with_context(|| format!("Input file: {}", input_file.display()))
                 .unwrap();
-            if finished_path.exists() {
-                fs::remove_dir_all(&finished_path).unwrap();
-            }
-            fs::create_dir(&finished_path).unwrap();
-            fs::rename(&repo_path, &finished_path).unwrap();
         }
         RunType::CreateUrls {
             data,
             output_dir,
-            limit,
             find,
             split,
-        } => create_urls::extract_urls(data, output_dir, limit, find, split),
-        RunType::MergeBranches { into, repos } => {
+            selection,
+            partitioning,
+            sampling,
+        } => create_urls::extract_urls(
+            data,
+            output_dir,
+            find,
+            split,
+            selection,
+            partitioning,
+            sampling,
+        )?,
+        RunType::MergeBranches {
+            into,
+            repos,
+            removed,
+        } => {
             // let into = fs::canonicalize(into)?;
             // To-do: handle errors here
             // let repos = repos.into_iter().map(|v| fs::canonicalize(v).unwrap()).collect();
-            combine::merge_all_branches(into, repos)?;
+            combine::merge_all_branches(into, repos, removed)?;
         }
+        RunType::MarkRemoved {
+            repo,
+            paths,
+            reason,
+        } => combine::mark_removed(repo, paths, reason)?,
         RunType::CreateRepository { name } => gitub::create_repository(name)?,
         RunType::Scan { repo: _, cmd: _ } => {
             // scanner::scan(repo, cmd)?;
         }
+        RunType::Stats {
+            data,
+            format,
+            top,
+            split,
+            partitioning,
+        } => stats::corpus_stats(data, top, split, partitioning, format)?,
+        RunType::Consistency { repo } => consistency::check_consistency(repo)?,
+        RunType::Follow { follow, inspection } => follow::follow(follow, &inspection)?,
+        RunType::BenchInspection {
+            corpus,
+            iterations,
+            inspection,
+        } => bench::bench_inspection(corpus, iterations, &inspection)?,
     }
     Ok(())
 }
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+
+// Calls that obfuscators use to turn an opaque blob back into code at import time.
+const LOADER_PATTERNS: &[&[u8]] = &[
+    b"exec(marshal.loads(",
+    b"exec(base64.b64decode(",
+    b"exec(zlib.decompress(",
+    b"exec(bytes.fromhex(",
+    b"exec(codecs.decode(",
+    b"exec(lzma.decompress(",
+    b"exec(bz2.decompress(",
+    b"eval(compile(",
+    b"__import__('marshal')",
+    b"__import__(\"marshal\")",
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ObfuscationReport {
+    pub score: f64,
+    pub entropy: f64,
+    pub line_count: usize,
+    pub max_line_length: usize,
+    pub average_line_length: f64,
+    pub string_literal_ratio: f64,
+    pub loader_calls: usize,
+}
+
+// A rough Python tokenizer that only knows about comments and string literals, which is all we
+// need to tell how much of a file is quoted data.
+#[derive(Clone, Copy)]
+enum Token {
+    Code,
+    Comment,
+    // One or two quotes in a row, which might be the start of a triple quoted string.
+    Quotes {
+        quote: u8,
+        count: u8,
+    },
+    String {
+        quote: u8,
+        triple: bool,
+        escaped: bool,
+        closing: u8,
+    },
+}
+
+/// Scores a Python source file between 0 and 1, where higher means more likely to be obfuscated.
+/// The contents are fed in chunks as they are read from the archive, so nothing needs buffering.
+pub struct ObfuscationScanner {
+    byte_counts: [u64; 256],
+    total: u64,
+    line_count: usize,
+    current_line_length: usize,
+    max_line_length: usize,
+    string_bytes: u64,
+    token: Token,
+    loader_calls: usize,
+    // The end of the previous chunk, so loader calls split across two chunks are still found.
+    tail: Vec<u8>,
+}
+
+impl Default for ObfuscationScanner {
+    fn default() -> Self {
+        ObfuscationScanner {
+            byte_counts: [0; 256],
+            total: 0,
+            line_count: 0,
+            current_line_length: 0,
+            max_line_length: 0,
+            string_bytes: 0,
+            token: Token::Code,
+            loader_calls: 0,
+            tail: vec![],
+        }
+    }
+}
+
+impl ObfuscationScanner {
+    pub fn update(&mut self, chunk: &[u8]) {
+        for byte in chunk {
+            self.byte_counts[*byte as usize] += 1;
+            if *byte == b'\n' {
+                self.line_count += 1;
+                self.max_line_length = self.max_line_length.max(self.current_line_length);
+                self.current_line_length = 0;
+            } else {
+                self.current_line_length += 1;
+            }
+            self.step(*byte);
+        }
+        self.total += chunk.len() as u64;
+        self.find_loader_calls(chunk);
+    }
+
+    pub fn finish(mut self) -> ObfuscationReport {
+        // Counted like `str::lines`, where a trailing newline doesn't start another line.
+        if self.current_line_length != 0 {
+            self.line_count += 1;
+        }
+        self.max_line_length = self.max_line_length.max(self.current_line_length);
+
+        let total = self.total as f64;
+        let entropy: f64 = self
+            .byte_counts
+            .iter()
+            .filter(|c| **c != 0)
+            .map(|c| {
+                let p = *c as f64 / total;
+                -p * p.log2()
+            })
+            .sum();
+        let average_line_length = total / self.line_count.max(1) as f64;
+        let string_literal_ratio = if self.total == 0 {
+            0.0
+        } else {
+            self.string_bytes as f64 / total
+        };
+
+        // No single signal is conclusive (plenty of real modules embed a big string, or call
+        // `exec`), so each one only contributes a weighted share of the final score. Regular source
+        // sits around 4.5-5 bits per byte, base64 and compressed payloads well above.
+        let score = 0.1 * scale(entropy, 5.0, 6.0)
+            + 0.15 * scale(self.max_line_length as f64, 500.0, 10_000.0)
+            + 0.15 * scale(average_line_length, 80.0, 1_000.0)
+            + 0.3 * scale(string_literal_ratio, 0.5, 0.9)
+            + 0.3 * if self.loader_calls > 0 { 1.0 } else { 0.0 };
+
+        ObfuscationReport {
+            score,
+            entropy,
+            line_count: self.line_count,
+            max_line_length: self.max_line_length,
+            average_line_length,
+            string_literal_ratio,
+            loader_calls: self.loader_calls,
+        }
+    }
+
+    fn step(&mut self, byte: u8) {
+        self.token = match self.token {
+            Token::Code => match byte {
+                b'#' => Token::Comment,
+                b'\'' | b'"' => Token::Quotes {
+                    quote: byte,
+                    count: 1,
+                },
+                _ => Token::Code,
+            },
+            Token::Comment if byte == b'\n' => Token::Code,
+            Token::Comment => Token::Comment,
+            Token::Quotes { quote, count: 1 } if byte == quote => Token::Quotes { quote, count: 2 },
+            Token::Quotes { quote, count: 1 } => {
+                self.token = Token::String {
+                    quote,
+                    triple: false,
+                    escaped: false,
+                    closing: 0,
+                };
+                return self.step(byte);
+            }
+            Token::Quotes { quote, .. } if byte == quote => Token::String {
+                quote,
+                triple: true,
+                escaped: false,
+                closing: 0,
+            },
+            // An empty string, so this byte is code again.
+            Token::Quotes { .. } => {
+                self.token = Token::Code;
+                return self.step(byte);
+            }
+            Token::String {
+                quote,
+                triple,
+                escaped: true,
+                ..
+            } => {
+                self.string_bytes += 1;
+                Token::String {
+                    quote,
+                    triple,
+                    escaped: false,
+                    closing: 0,
+                }
+            }
+            Token::String {
+                triple: false,
+                quote,
+                ..
+            } => match byte {
+                b'\n' => Token::Code,
+                b if b == quote => Token::Code,
+                b => {
+                    self.string_bytes += 1;
+                    Token::String {
+                        quote,
+                        triple: false,
+                        escaped: b == b'\\',
+                        closing: 0,
+                    }
+                }
+            },
+            Token::String {
+                quote, closing: 2, ..
+            } if byte == quote => Token::Code,
+            Token::String { quote, closing, .. } if byte == quote => Token::String {
+                quote,
+                triple: true,
+                escaped: false,
+                closing: closing + 1,
+            },
+            Token::String { quote, closing, .. } => {
+                // Quotes that turned out not to close the string were part of it after all.
+                self.string_bytes += 1 + closing as u64;
+                Token::String {
+                    quote,
+                    triple: true,
+                    escaped: byte == b'\\',
+                    closing: 0,
+                }
+            }
+        }
+    }
+
+    fn find_loader_calls(&mut self, chunk: &[u8]) {
+        let keep = LOADER_PATTERNS.iter().map(|p| p.len()).max().unwrap() - 1;
+
+        // Calls that start in the tail of the previous chunk and finish in this one.
+        let mut boundary = std::mem::take(&mut self.tail);
+        let tail_length = boundary.len();
+        boundary.extend_from_slice(&chunk[..chunk.len().min(keep)]);
+        self.loader_calls += count_loader_calls(&boundary, tail_length, tail_length);
+        self.loader_calls += count_loader_calls(chunk, chunk.len(), 0);
+
+        let tail_start = boundary.len().saturating_sub(keep);
+        self.tail = if chunk.len() >= keep {
+            chunk[chunk.len() - keep..].to_vec()
+        } else {
+            boundary[tail_start..].to_vec()
+        };
+    }
+}
+
+// Count loader calls in `contents` that start before `starts_before` and end after `ends_after`.
+fn count_loader_calls(contents: &[u8], starts_before: usize, ends_after: usize) -> usize {
+    contents[..starts_before]
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'e' || **b == b'_')
+        .map(|(idx, _)| {
+            LOADER_PATTERNS
+                .iter()
+                .filter(|pattern| {
+                    idx + pattern.len() > ends_after && contents[idx..].starts_with(pattern)
+                })
+                .count()
+        })
+        .sum()
+}
+
+fn scale(value: f64, low: f64, high: f64) -> f64 {
+    ((value - low) / (high - low)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(chunks: &[&[u8]]) -> ObfuscationReport {
+        let mut scanner = ObfuscationScanner::default();
+        for chunk in chunks {
+            scanner.update(chunk);
+        }
+        scanner.finish()
+    }
+
+    #[test]
+    fn regular_source_scores_low() {
+        let report = scan(&[b"import os
+
+
+def main():
+    # Print the cwd
+    print(os.getcwd())
+"]);
+        assert!(report.score < 0.1, "{report:?}");
+        assert_eq!(report.loader_calls, 0);
+        assert_eq!(report.string_literal_ratio, 0.0);
+    }
+
+    #[test]
+    fn empty_file() {
+        let report = scan(&[]);
+        assert_eq!(report.score, 0.0);
+        assert_eq!(report.string_literal_ratio, 0.0);
+    }
+
+    #[test]
+    fn packed_payload_scores_high() {
+        let payload = "QUJD".repeat(5_000);
+        let source = format!("import base64\nexec(base64.b64decode('{payload}'))\n");
+        let report = scan(&[source.as_bytes()]);
+        assert_eq!(report.loader_calls, 1);
+        assert!(report.string_literal_ratio > 0.99, "{report:?}");
+        assert!(report.score >= 0.6, "{report:?}");
+    }
+
+    #[test]
+    fn loader_call_split_across_chunks() {
+        let source = b"exec(zlib.decompress(b''))";
+        for split in 0..=source.len() {
+            let (a, b) = source.split_at(split);
+            assert_eq!(scan(&[a, b]).loader_calls, 1, "split at {split}");
+        }
+        assert_eq!(scan(&[b"exec(mar", b"sh", b"al.loads(x))"]).loader_calls, 1);
+    }
+
+    #[test]
+    fn string_literals() {
+        // Only the contents of strings count, not their quotes or comments containing quotes.
+        let source = b"a = 'xy'\nb = \"\"\"z'w\"\"\"  # 'not a string'\n";
+        let report = scan(&[source]);
+        assert_eq!(report.string_literal_ratio, 5.0 / source.len() as f64);
+
+        let source = b"a = ''\nb = 'it\\'s'\n";
+        let report = scan(&[source]);
+        assert_eq!(report.string_literal_ratio, 5.0 / source.len() as f64);
+    }
+
+    #[test]
+    fn line_lengths() {
+        let report = scan(&[b"ab\n", b"abcd\na"]);
+        assert_eq!(report.max_line_length, 4);
+    }
+
+    #[test]
+    fn line_count_matches_lines() {
+        for source in [
+            "",
+            "\n",
+            "a",
+            "a\n",
+            "a\nb",
+            "a\n\nb\n",
+            "a\r\nb\r\n",
+            "a\n\r",
+        ] {
+            let report = scan(&[source.as_bytes()]);
+            assert_eq!(report.line_count, source.lines().count(), "{source:?}");
+        }
+    }
+}
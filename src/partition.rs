@@ -0,0 +1,244 @@
+use crate::create_urls::DownloadJob;
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Written into the output directory of `create-urls`, next to the partitions it describes.
+pub const PARTITION_MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PartitionStrategy {
+    /// A fixed number of jobs per partition, given by --split
+    #[default]
+    Count,
+    /// Roughly --partition-bytes of archives per partition
+    Bytes,
+    /// One partition per calendar --window
+    Window,
+    /// --partitions partitions, keyed by the hash of the package name
+    Hash,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Window {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PartitionOptions {
+    #[arg(long, value_enum, default_value_t)]
+    pub partition_by: PartitionStrategy,
+    #[arg(long, default_value = "10000000000")]
+    pub partition_bytes: u64,
+    #[arg(long, value_enum, default_value_t)]
+    pub window: Window,
+    #[arg(long, default_value = "64")]
+    pub partitions: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PartitionManifest {
+    pub strategy: PartitionStrategy,
+    pub partitions: Vec<PartitionManifestEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PartitionManifestEntry {
+    pub file: String,
+    pub jobs: usize,
+    /// Taken from the sizes in the PyPI metadata, so files without one aren't counted.
+    pub bytes: u64,
+    /// Missing if none of the jobs have an upload time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earliest: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest: Option<DateTime<Utc>>,
+    /// The sha256 of the partition file, once it has been written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl PartitionManifestEntry {
+    pub fn new(file: String, jobs: &[DownloadJob]) -> Self {
+        let (earliest, latest) = jobs
+            .iter()
+            .filter_map(|j| j.uploaded_on)
+            .minmax()
+            .into_option()
+            .unzip();
+        PartitionManifestEntry {
+            file,
+            jobs: jobs.len(),
+            bytes: jobs
+                .iter()
+                .map(|j| j.metadata.size.unwrap_or_default())
+                .sum(),
+            earliest,
+            latest,
+            sha256: None,
+        }
+    }
+}
+
+// The time a partition starts at, formatted without the spaces and colons that some filesystems
+// and tools don't like.
+fn partition_name(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H-%M-%SZ").to_string()
+}
+
+/// Write jobs to a partition file with one JSON object per line, returning its sha256.
+pub fn write_partition(path: &Path, jobs: &[DownloadJob]) -> anyhow::Result<String> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut hasher = Sha256::new();
+    for job in jobs {
+        let mut line = serde_json::to_vec(job)?;
+        line.push(b'\n');
+        hasher.update(&line);
+        writer.write_all(&line)?;
+    }
+    writer.flush()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Stream the jobs in a partition file, or from stdin if the path is `-`. Partitions written as a
+/// single JSON array by older versions are still accepted, but are read in one go.
+pub fn read_partition(
+    path: &Path,
+) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<DownloadJob>> + Send>> {
+    let mut reader: Box<dyn BufRead + Send> = if path == Path::new("-") {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        let file = File::open(path).with_context(|| format!("Error opening {}", path.display()))?;
+        Box::new(BufReader::new(file))
+    };
+
+    let is_array = loop {
+        let buffer = reader.fill_buf()?;
+        match buffer.iter().position(|c| !c.is_ascii_whitespace()) {
+            Some(idx) => {
+                let is_array = buffer[idx] == b'[';
+                reader.consume(idx);
+                break is_array;
+            }
+            None if buffer.is_empty() => break false,
+            None => {
+                let length = buffer.len();
+                reader.consume(length);
+            }
+        }
+    };
+    if is_array {
+        let jobs: Vec<DownloadJob> = serde_json::from_reader(reader)?;
+        return Ok(Box::new(jobs.into_iter().map(Ok)));
+    }
+
+    Ok(Box::new(reader.lines().enumerate().filter_map(
+        |(idx, line)| {
+            match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(
+                    serde_json::from_str(&line)
+                        .with_context(|| format!("Invalid job on line {}", idx + 1)),
+                ),
+                Err(e) => Some(Err(e.into())),
+            }
+        },
+    )))
+}
+
+/// Split jobs sorted by upload time into named partitions, each of which is still sorted by upload
+/// time.
+pub fn partition(
+    jobs: Vec<DownloadJob>,
+    split: usize,
+    options: &PartitionOptions,
+) -> Vec<(String, Vec<DownloadJob>)> {
+    let partitions: Vec<Vec<_>> = match options.partition_by {
+        PartitionStrategy::Count => jobs
+            .into_iter()
+            .chunks(split)
+            .into_iter()
+            .map(|v| v.collect())
+            .collect(),
+        PartitionStrategy::Bytes => {
+            let mut partitions = vec![];
+            let mut current: Vec<DownloadJob> = vec![];
+            let mut current_bytes = 0;
+            for job in jobs {
+                let size = job.metadata.size.unwrap_or_default();
+                if !current.is_empty() && current_bytes + size > options.partition_bytes {
+                    partitions.push(std::mem::take(&mut current));
+                    current_bytes = 0;
+                }
+                current_bytes += size;
+                current.push(job);
+            }
+            if !current.is_empty() {
+                partitions.push(current);
+            }
+            partitions
+        }
+        PartitionStrategy::Window => jobs
+            .into_iter()
+            .group_by(|job| {
+                job.uploaded_on
+                    .map(|time| window_start(time.date_naive(), options.window))
+            })
+            .into_iter()
+            .map(|(_, v)| v.collect())
+            .collect(),
+        PartitionStrategy::Hash => {
+            let mut partitions: Vec<Vec<_>> =
+                (0..options.partitions.max(1)).map(|_| vec![]).collect();
+            let count = partitions.len();
+            for job in jobs {
+                partitions[name_bucket(&job.normalized_name, count)].push(job);
+            }
+            // The first upload in two buckets can be at the same time, so name them by bucket.
+            return partitions
+                .into_iter()
+                .enumerate()
+                .filter(|(_, p)| !p.is_empty())
+                .map(|(idx, p)| (format!("hash-{idx:04}"), p))
+                .collect();
+        }
+    };
+    // Partitions are named by their position, as several can start in the same second (a bulk
+    // upload of wheels split by size, say) and files from simple indexes might not have an upload
+    // time at all. The time is only there to make the names easier to read.
+    partitions
+        .into_iter()
+        .enumerate()
+        .map(
+            |(idx, p)| match p.iter().filter_map(|v| v.uploaded_on).min() {
+                Some(first_job_time) => (format!("{idx:05}-{}", partition_name(first_job_time)), p),
+                None => (format!("{idx:05}"), p),
+            },
+        )
+        .collect()
+}
+
+fn window_start(date: NaiveDate, window: Window) -> NaiveDate {
+    match window {
+        Window::Day => date,
+        Window::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        Window::Month => date.with_day(1).unwrap(),
+    }
+}
+
+// std's hashers aren't guaranteed to be stable between releases, and a package should always end
+// up in the same partition so that re-running an import doesn't shuffle everything around.
+fn name_bucket(name: &str, count: usize) -> usize {
+    let digest = Sha256::digest(name.as_bytes());
+    let value = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (value % count as u64) as usize
+}
@@ -0,0 +1,266 @@
+use crate::create_urls::DownloadJob;
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    reason: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    name_globs: Vec<String>,
+    name_regex: Option<String>,
+    version_regex: Option<String>,
+    filename_regex: Option<String>,
+    #[serde(default)]
+    package_types: Vec<String>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub reason: String,
+    names: HashSet<String>,
+    name_globs: Option<GlobSet>,
+    name_regex: Option<Regex>,
+    version_regex: Option<Regex>,
+    filename_regex: Option<Regex>,
+    package_types: Vec<String>,
+    max_bytes: Option<u64>,
+}
+
+impl Rule {
+    fn new(definition: RuleDefinition) -> anyhow::Result<Self> {
+        // Every field narrows what a rule matches, so a rule without any would match every file.
+        if definition.names.is_empty()
+            && definition.name_globs.is_empty()
+            && definition.name_regex.is_none()
+            && definition.version_regex.is_none()
+            && definition.filename_regex.is_none()
+            && definition.package_types.is_empty()
+        {
+            anyhow::bail!(
+                "Rule {:?} has nothing to match on. Use name_globs = [\"*\"] to match every package",
+                definition.reason
+            );
+        }
+        let regex = |pattern: Option<String>| pattern.as_deref().map(Regex::new).transpose();
+        let name_globs = if definition.name_globs.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for glob in &definition.name_globs {
+                builder.add(Glob::new(glob)?);
+            }
+            Some(builder.build()?)
+        };
+        Ok(Rule {
+            names: definition.names.into_iter().collect(),
+            name_globs,
+            name_regex: regex(definition.name_regex)?,
+            version_regex: regex(definition.version_regex)?,
+            filename_regex: regex(definition.filename_regex)?,
+            package_types: definition.package_types,
+            max_bytes: definition.max_bytes,
+            reason: definition.reason,
+        })
+    }
+
+    fn matches(&self, job: &DownloadJob) -> bool {
+        (self.names.is_empty() || self.names.contains(&job.name))
+            && self
+                .name_globs
+                .as_ref()
+                .is_none_or(|g| g.is_match(&job.name))
+            && self
+                .name_regex
+                .as_ref()
+                .is_none_or(|r| r.is_match(&job.name))
+            && self
+                .version_regex
+                .as_ref()
+                .is_none_or(|r| r.is_match(&job.version))
+            && self
+                .filename_regex
+                .as_ref()
+                .is_none_or(|r| r.is_match(job.package_filename()))
+            && (self.package_types.is_empty()
+                || job
+                    .metadata
+                    .package_type
+                    .as_ref()
+                    .is_some_and(|t| self.package_types.contains(t)))
+    }
+}
+
+pub struct ExclusionPolicy {
+    rules: Vec<Rule>,
+}
+
+impl ExclusionPolicy {
+    /// Load a policy from a TOML file, or the built in policy if no file is given.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let contents = match path {
+            None => include_str!("default_policy.toml").to_string(),
+            Some(path) => fs::read_to_string(path)
+                .with_context(|| format!("Error reading policy {}", path.display()))?,
+        };
+        Self::from_toml(&contents)
+    }
+
+    fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let policy: PolicyFile = toml::from_str(contents)?;
+        let rules = policy
+            .rules
+            .into_iter()
+            .map(Rule::new)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(ExclusionPolicy { rules })
+    }
+
+    /// Split every file uploaded for a single package into the ones to keep and the ones that are
+    /// excluded, along with the rule that excluded them. Byte budgets are spent in upload order.
+    pub fn evaluate(
+        &self,
+        mut jobs: Vec<DownloadJob>,
+    ) -> (Vec<DownloadJob>, Vec<(&Rule, DownloadJob)>) {
+        jobs.sort();
+        let mut spent = vec![0; self.rules.len()];
+        let mut kept = vec![];
+        let mut excluded = vec![];
+
+        for job in jobs {
+            let matching: Vec<_> = self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.matches(&job))
+                .collect();
+            let size = job.metadata.size.unwrap_or_default();
+
+            let excluded_by = matching.iter().find(|(idx, rule)| match rule.max_bytes {
+                None => true,
+                Some(budget) => spent[*idx] + size > budget,
+            });
+            match excluded_by {
+                Some((_, rule)) => excluded.push((*rule, job)),
+                None => {
+                    for (idx, _) in matching {
+                        spent[idx] += size;
+                    }
+                    kept.push(job);
+                }
+            }
+        }
+        (kept, excluded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(name: &str, version: &str, filename: &str, size: u64) -> DownloadJob {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "version": version,
+            "url": format!("https://files.pythonhosted.org/packages/{filename}"),
+            "package_type": "sdist",
+            "size": size,
+        }))
+        .unwrap()
+    }
+
+    fn excluded(policy: &str, jobs: Vec<DownloadJob>) -> Vec<String> {
+        let policy = ExclusionPolicy::from_toml(policy).unwrap();
+        let (_, excluded) = policy.evaluate(jobs);
+        excluded
+            .into_iter()
+            .map(|(_, job)| job.package_filename().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn name_globs() {
+        let policy = r#"
+            [[rule]]
+            reason = "nightlies"
+            name_globs = ["*-nightly"]
+        "#;
+        let jobs = vec![
+            job("tf-nightly", "1.0", "tf-nightly-1.0.tar.gz", 10),
+            job("tf", "1.0", "tf-1.0.tar.gz", 10),
+        ];
+        assert_eq!(excluded(policy, jobs), ["tf-nightly-1.0.tar.gz"]);
+    }
+
+    #[test]
+    fn regexes() {
+        let policy = r#"
+            [[rule]]
+            reason = "dev releases of foo"
+            name_regex = "^foo$"
+            version_regex = "dev"
+        "#;
+        let jobs = vec![
+            job("foo", "1.0.dev1", "foo-1.0.dev1.tar.gz", 10),
+            job("foo", "1.0", "foo-1.0.tar.gz", 10),
+            job("foobar", "1.0.dev1", "foobar-1.0.dev1.tar.gz", 10),
+        ];
+        assert_eq!(excluded(policy, jobs), ["foo-1.0.dev1.tar.gz"]);
+
+        let policy = r#"
+            [[rule]]
+            reason = "zips"
+            filename_regex = "\\.zip$"
+        "#;
+        let jobs = vec![
+            job("foo", "1.0", "foo-1.0.zip", 10),
+            job("foo", "1.0", "foo-1.0.tar.gz", 10),
+        ];
+        assert_eq!(excluded(policy, jobs), ["foo-1.0.zip"]);
+    }
+
+    #[test]
+    fn max_bytes() {
+        let policy = r#"
+            [[rule]]
+            reason = "too big"
+            names = ["foo"]
+            max_bytes = 25
+        "#;
+        let jobs = vec![
+            job("foo", "1.0", "foo-1.0.tar.gz", 10),
+            job("foo", "2.0", "foo-2.0.tar.gz", 10),
+            job("foo", "3.0", "foo-3.0.tar.gz", 10),
+            job("foo", "4.0", "foo-4.0.tar.gz", 5),
+        ];
+        assert_eq!(excluded(policy, jobs), ["foo-3.0.tar.gz"]);
+    }
+
+    #[test]
+    fn rule_without_matchers() {
+        let policy = r#"
+            [[rule]]
+            reason = "everything"
+        "#;
+        assert!(ExclusionPolicy::from_toml(policy).is_err());
+    }
+
+    #[test]
+    fn default_policy() {
+        ExclusionPolicy::load(None).unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+use crate::setup_py::SetupPy;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use toml::{Table, Value};
+
+/// Metadata read from inside a distribution file while it is imported, rather than from PyPI.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReleaseMetadata {
+    /// A wheel's `METADATA`, or an sdist's `PKG-INFO` with any gaps filled in from its
+    /// `pyproject.toml`, `setup.cfg` and `setup.py`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core: Option<CoreMetadata>,
+    /// The wheel's `WHEEL` file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wheel: Option<WheelInfo>,
+    /// The `[build-system]` table of an sdist's `pyproject.toml`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_system: Option<BuildSystem>,
+    /// What could be read from the arguments to `setup()` in an sdist's `setup.py`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_py: Option<SetupPy>,
+    /// The files `core` was read from, in order of precedence.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+}
+
+impl ReleaseMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.core.is_none()
+            && self.wheel.is_none()
+            && self.build_system.is_none()
+            && self.setup_py.is_none()
+    }
+
+    /// Read whatever metadata files were captured from an archive, and its top level `setup.py`,
+    /// returning the wheel's RECORD separately so the imported files can be checked against it.
+    pub fn from_files(
+        files: &[(String, Vec<u8>)],
+        setup_py: Option<&str>,
+    ) -> (Self, Option<Record>) {
+        let mut metadata = ReleaseMetadata::default();
+        let mut record = None;
+        let mut pkg_info = None;
+        let mut pyproject = None;
+        let mut setup_cfg = None;
+        for (path, contents) in files {
+            let contents = String::from_utf8_lossy(contents);
+            match path.rsplit('/').next() {
+                Some("METADATA") => metadata.core = Some(CoreMetadata::parse(&contents)),
+                Some("WHEEL") => metadata.wheel = Some(WheelInfo::parse(&contents)),
+                Some("RECORD") => record = Some(Record::parse(&contents)),
+                Some("PKG-INFO") => pkg_info = Some(CoreMetadata::parse(&contents)),
+                Some("pyproject.toml") => pyproject = contents.parse::<Table>().ok(),
+                Some("setup.cfg") => setup_cfg = Some(parse_setup_cfg(&contents)),
+                _ => {}
+            }
+        }
+        if metadata.core.is_some() {
+            metadata.sources.push("METADATA".to_string());
+            // A wheel's METADATA is complete, and anything else at its top level belongs to
+            // one of the packages inside it.
+            return (metadata, record);
+        }
+
+        if let Some(pyproject) = &pyproject {
+            metadata.build_system = BuildSystem::from_pyproject(pyproject);
+        }
+        let mut sources = vec![
+            ("PKG-INFO", pkg_info),
+            (
+                "pyproject.toml",
+                pyproject.as_ref().and_then(CoreMetadata::from_pyproject),
+            ),
+            (
+                "setup.cfg",
+                setup_cfg.as_ref().map(CoreMetadata::from_setup_cfg),
+            ),
+        ];
+        if let Some(setup_py) = setup_py {
+            let analysis = SetupPy::analyse(setup_py);
+            if analysis.is_resolved() {
+                sources.push(("setup.py", Some(CoreMetadata::from_setup_py(&analysis))));
+            }
+            metadata.setup_py = Some(analysis).filter(|a| !a.is_empty());
+        }
+        for (source, core) in sources {
+            let Some(core) = core else {
+                continue;
+            };
+            metadata.sources.push(source.to_string());
+            match &mut metadata.core {
+                None => metadata.core = Some(core),
+                Some(existing) => existing.fill_from(core),
+            }
+        }
+        (metadata, record)
+    }
+}
+
+/// Packaging metadata that is kept back while an archive is read, to be parsed once it has been
+/// imported. Only the top level of an archive counts (a wheel's `.dist-info`, an egg's
+/// `EGG-INFO` or an sdist's root directory), not copies vendored further down.
+pub fn is_metadata_file(path: &str) -> bool {
+    match path.split_once('/') {
+        Some((dir, file)) if dir.ends_with(".dist-info") => {
+            matches!(file, "METADATA" | "WHEEL" | "RECORD")
+        }
+        Some((_, file)) => matches!(file, "PKG-INFO" | "pyproject.toml" | "setup.cfg"),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct BuildSystem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_backend: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+}
+
+impl BuildSystem {
+    fn from_pyproject(pyproject: &Table) -> Option<Self> {
+        let table = pyproject.get("build-system")?.as_table()?;
+        Some(BuildSystem {
+            build_backend: string(table.get("build-backend")),
+            requires: strings(table.get("requires")),
+        })
+    }
+}
+
+/// The core metadata fields (https://packaging.python.org/specifications/core-metadata/) that are
+/// worth querying across releases.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct CoreMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// The `License-Expression`, or the first line of `License`, which some projects fill with
+    /// the whole text of their license.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires_python: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub requires_dist: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub provides_extra: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classifiers: Vec<String>,
+}
+
+impl CoreMetadata {
+    pub fn parse(text: &str) -> Self {
+        let mut metadata = CoreMetadata::default();
+        let mut license_expression = None;
+        for (name, value) in parse_headers(text) {
+            match name.as_str() {
+                "metadata-version" => metadata.metadata_version = Some(value),
+                "name" => metadata.name = Some(value),
+                "version" => metadata.version = Some(value),
+                "summary" => metadata.summary = Some(value),
+                "license" => {
+                    metadata.license = value.lines().next().map(str::to_string);
+                }
+                "license-expression" => license_expression = Some(value),
+                "requires-python" => metadata.requires_python = Some(value),
+                "requires-dist" => metadata.requires_dist.push(value),
+                "provides-extra" => metadata.provides_extra.push(value),
+                "classifier" => metadata.classifiers.push(value),
+                _ => {}
+            }
+        }
+        if license_expression.is_some() {
+            metadata.license = license_expression;
+        }
+        metadata.license = metadata.license.filter(|l| !l.is_empty() && l != "UNKNOWN");
+        metadata
+    }
+
+    // The `[project]` table from PEP 621. Fields listed as `dynamic` are simply missing.
+    fn from_pyproject(pyproject: &Table) -> Option<Self> {
+        let project = pyproject.get("project")?.as_table()?;
+        let license = match project.get("license") {
+            Some(Value::Table(license)) => string(license.get("text")),
+            license => string(license),
+        };
+        let mut metadata = CoreMetadata {
+            metadata_version: None,
+            name: string(project.get("name")),
+            version: string(project.get("version")),
+            summary: string(project.get("description")),
+            license: license.and_then(|l| l.lines().next().map(str::to_string)),
+            requires_python: string(project.get("requires-python")),
+            requires_dist: strings(project.get("dependencies")),
+            provides_extra: vec![],
+            classifiers: strings(project.get("classifiers")),
+        };
+        if let Some(extras) = project
+            .get("optional-dependencies")
+            .and_then(Value::as_table)
+        {
+            for (extra, requirements) in extras {
+                metadata.add_extra(extra, strings(Some(requirements)));
+            }
+        }
+        Some(metadata)
+    }
+
+    // Values setuptools reads from elsewhere (`attr:` and `file:`) can't be resolved without
+    // the rest of the tree, so they're left out.
+    fn from_setup_cfg(config: &SetupCfg) -> Self {
+        let value = |section: &str, key: &str| {
+            config
+                .get(section)
+                .and_then(|s| s.get(key))
+                .filter(|v| !v.starts_with("attr:") && !v.starts_with("file:"))
+                .cloned()
+        };
+        let list = |section: &str, key: &str| -> Vec<String> {
+            value(section, key)
+                .map(|v| {
+                    v.lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mut metadata = CoreMetadata {
+            metadata_version: None,
+            name: value("metadata", "name"),
+            version: value("metadata", "version"),
+            summary: value("metadata", "description"),
+            license: value("metadata", "license"),
+            requires_python: value("options", "python_requires"),
+            requires_dist: list("options", "install_requires"),
+            provides_extra: vec![],
+            classifiers: list("metadata", "classifiers")
+                .iter()
+                .flat_map(|l| l.split(','))
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+        if let Some(extras) = config.get("options.extras_require") {
+            for extra in extras.keys() {
+                metadata.add_extra(extra, list("options.extras_require", extra));
+            }
+        }
+        metadata
+    }
+
+    fn from_setup_py(analysis: &SetupPy) -> Self {
+        let mut metadata = CoreMetadata {
+            requires_python: analysis.python_requires.clone(),
+            requires_dist: analysis.install_requires.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        for (extra, requirements) in analysis.extras_require.iter().flatten() {
+            metadata.add_extra(extra, requirements.clone());
+        }
+        metadata
+    }
+
+    // Optional dependencies are written as they would be in Requires-Dist.
+    fn add_extra(&mut self, extra: &str, requirements: Vec<String>) {
+        self.provides_extra.push(extra.to_string());
+        for requirement in requirements {
+            self.requires_dist.push(match requirement.split_once(';') {
+                Some((requirement, marker)) => format!(
+                    "{}; ({}) and extra == \"{extra}\"",
+                    requirement.trim(),
+                    marker.trim()
+                ),
+                None => format!("{}; extra == \"{extra}\"", requirement.trim()),
+            });
+        }
+    }
+
+    // Fill in anything missing from a less authoritative source. PKG-INFO written by older
+    // setuptools has no Requires-Dist, for example, even when setup.cfg lists dependencies.
+    fn fill_from(&mut self, other: CoreMetadata) {
+        fn fill<T>(value: &mut Option<T>, other: Option<T>) {
+            if value.is_none() {
+                *value = other;
+            }
+        }
+        fn fill_list<T>(value: &mut Vec<T>, other: Vec<T>) {
+            if value.is_empty() {
+                *value = other;
+            }
+        }
+        fill(&mut self.name, other.name);
+        fill(&mut self.version, other.version);
+        fill(&mut self.summary, other.summary);
+        fill(&mut self.license, other.license);
+        fill(&mut self.requires_python, other.requires_python);
+        if self.requires_dist.is_empty() {
+            self.requires_dist = other.requires_dist;
+            self.provides_extra = other.provides_extra;
+        }
+        fill_list(&mut self.classifiers, other.classifiers);
+    }
+}
+
+fn string(value: Option<&Value>) -> Option<String> {
+    value.and_then(Value::as_str).map(str::to_string)
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .flat_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+type SetupCfg = HashMap<String, HashMap<String, String>>;
+
+// Just enough of configparser's INI dialect for setup.cfg: `key = value` or `key: value`, values
+// continued on indented lines, and `#` or `;` comments.
+fn parse_setup_cfg(text: &str) -> SetupCfg {
+    let mut config = SetupCfg::new();
+    let mut section = String::new();
+    let mut key: Option<String> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = key
+                .as_ref()
+                .and_then(|k| config.get_mut(&section)?.get_mut(k))
+            {
+                value.push('\n');
+                value.push_str(trimmed);
+            }
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            section = name.trim().to_string();
+            key = None;
+            continue;
+        }
+        let Some(idx) = trimmed.find(['=', ':']) else {
+            continue;
+        };
+        let name = trimmed[..idx].trim().to_lowercase().replace('-', "_");
+        let value = trimmed[idx + 1..].trim().to_string();
+        config
+            .entry(section.clone())
+            .or_default()
+            .insert(name.clone(), value);
+        key = Some(name);
+    }
+    config
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct WheelInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wheel_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_is_purelib: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl WheelInfo {
+    pub fn parse(text: &str) -> Self {
+        let mut info = WheelInfo::default();
+        for (name, value) in parse_headers(text) {
+            match name.as_str() {
+                "wheel-version" => info.wheel_version = Some(value),
+                "generator" => info.generator = Some(value),
+                "root-is-purelib" => {
+                    info.root_is_purelib = Some(value.eq_ignore_ascii_case("true"))
+                }
+                "tag" => info.tags.push(value),
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+// Both METADATA and WHEEL are RFC 822 style headers. Continuation lines are indented, and the
+// body (the long description, in METADATA) starts after the first blank line.
+fn parse_headers(text: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// A wheel's RECORD, mapping each path to its sha256 as a hex string. Paths listed without a
+/// hash, or with some other algorithm, map to `None` and can't be checked.
+pub struct Record(HashMap<String, Option<String>>);
+
+/// How an imported file compares to the wheel's RECORD, when it doesn't simply match.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum RecordCheck {
+    Mismatch { expected: String },
+    Unlisted,
+}
+
+impl Record {
+    pub fn parse(text: &str) -> Self {
+        let mut hashes = HashMap::new();
+        for line in text.lines() {
+            // `path,sha256=<urlsafe base64>,size`. Only the path can contain commas, in which case
+            // it is quoted.
+            let mut fields = line.rsplitn(3, ',');
+            let (Some(_size), Some(hash), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let path = match path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\"\"", "\""),
+                None => path.to_string(),
+            };
+            let sha256 = hash
+                .strip_prefix("sha256=")
+                .and_then(|digest| URL_SAFE_NO_PAD.decode(digest.trim_end_matches('=')).ok())
+                .map(|digest| digest.iter().map(|b| format!("{b:02x}")).collect());
+            hashes.insert(path, sha256);
+        }
+        Record(hashes)
+    }
+
+    /// Returns `None` if the file matches, or can't be checked.
+    pub fn check(&self, path: &str, sha256: &str) -> Option<RecordCheck> {
+        match self.0.get(path) {
+            None => Some(RecordCheck::Unlisted),
+            Some(Some(expected)) if expected != sha256 => Some(RecordCheck::Mismatch {
+                expected: expected.clone(),
+            }),
+            Some(_) => None,
+        }
+    }
+}
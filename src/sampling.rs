@@ -0,0 +1,78 @@
+use crate::create_urls::DownloadJob;
+use chrono::Datelike;
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+#[derive(clap::Args, Debug)]
+pub struct SamplingOptions {
+    /// Only output this many partitions, from a random sample of packages
+    #[arg(long, short)]
+    pub limit: Option<usize>,
+    /// Seed the sample, so the same inputs always produce the same partitions
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Sample files evenly across upload years or size buckets, rather than by package
+    #[arg(long, value_enum, default_value_t)]
+    pub stratify: Stratify,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Stratify {
+    #[default]
+    None,
+    Year,
+    Size,
+}
+
+impl SamplingOptions {
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+/// Sample `count` jobs, allocating them to each stratum in proportion to its size. Every stratum
+/// gets at least one job, so a sample still covers years or sizes that are rare on PyPI.
+pub fn stratified_sample(
+    jobs: Vec<DownloadJob>,
+    count: usize,
+    stratify: Stratify,
+    rng: &mut StdRng,
+) -> Vec<DownloadJob> {
+    let total = jobs.len();
+    if stratify == Stratify::None || total <= count {
+        return jobs;
+    }
+    // Strata are visited in a fixed order so the same seed always gives the same sample.
+    let strata = jobs
+        .into_iter()
+        .into_group_map_by(|job| stratum(job, stratify))
+        .into_iter()
+        .sorted_by_key(|(key, _)| *key);
+
+    let mut sample = vec![];
+    for (_, mut stratum_jobs) in strata {
+        let allocation = (stratum_jobs.len() * count / total).max(1);
+        stratum_jobs.shuffle(rng);
+        stratum_jobs.truncate(allocation);
+        sample.extend(stratum_jobs);
+    }
+    sample
+}
+
+fn stratum(job: &DownloadJob, stratify: Stratify) -> i64 {
+    match stratify {
+        Stratify::None => 0,
+        // Files without an upload time go first.
+        Stratify::Year => job.uploaded_on.map_or(-1, |time| time.year() as i64),
+        // Orders of magnitude, so 10KB-100KB, 100KB-1MB and so on. Files without a size go first.
+        Stratify::Size => job
+            .metadata
+            .size
+            .map_or(-1, |size| (size.max(1) as f64).log10().floor() as i64),
+    }
+}
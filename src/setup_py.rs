@@ -0,0 +1,670 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The arguments to `setup()` that can be read from `setup.py` without running it. Only literals
+/// are resolved, along with module level variables that are assigned a literal exactly once.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct SetupPy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_requires: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extras_require: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub python_requires: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry_points: Option<BTreeMap<String, Vec<String>>>,
+    /// Arguments that are passed but couldn't be resolved, or why `setup()` couldn't be read.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<Unresolved>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Unresolved {
+    pub argument: String,
+    pub reason: String,
+}
+
+const ARGUMENTS: &[&str] = &[
+    "install_requires",
+    "extras_require",
+    "python_requires",
+    "entry_points",
+];
+
+impl SetupPy {
+    pub fn analyse(source: &str) -> Self {
+        let mut result = SetupPy::default();
+        let mut unresolved = |argument: &str, reason: String| {
+            result.unresolved.push(Unresolved {
+                argument: argument.to_string(),
+                reason,
+            })
+        };
+        let tokens = match tokenize(source) {
+            Ok(tokens) => tokens,
+            Err(reason) => {
+                unresolved("setup", reason);
+                return result;
+            }
+        };
+        let module = Module::new(source, &tokens);
+        let Some(call) = module.setup_call() else {
+            unresolved("setup", "No call to setup()".to_string());
+            return result;
+        };
+
+        let mut found = HashMap::new();
+        let mut splat = None;
+        for (keyword, value) in module.arguments(call) {
+            match keyword {
+                Some(keyword) if ARGUMENTS.contains(&keyword) => {
+                    found.insert(keyword, value);
+                }
+                Some(_) => {}
+                None if matches!(tokens.get(value.start), Some(t) if t.kind == Kind::Op("**")) => {
+                    splat = Some(module.snippet(value.start + 1..value.end));
+                }
+                None => {}
+            }
+        }
+
+        for argument in ARGUMENTS {
+            let Some(range) = found.remove(argument) else {
+                // Could be anything, so it's worth knowing about.
+                if let Some(splat) = &splat {
+                    unresolved(argument, format!("Maybe passed in **{splat}"));
+                }
+                continue;
+            };
+            let value = module.evaluate(range.clone());
+            let resolved = match *argument {
+                "install_requires" => value
+                    .and_then(|v| string_list(&v))
+                    .map(|v| result.install_requires = Some(v)),
+                "python_requires" => value.and_then(|v| match v {
+                    Literal::Str(s) => {
+                        result.python_requires = Some(s);
+                        Ok(())
+                    }
+                    _ => Err("Expected a string".to_string()),
+                }),
+                "extras_require" => value
+                    .and_then(|v| string_list_dict(&v))
+                    .map(|v| result.extras_require = Some(v)),
+                _ => value
+                    .and_then(|v| match v {
+                        // The same INI format as entry_points.txt.
+                        Literal::Str(s) => Ok(entry_points_ini(&s)),
+                        v => string_list_dict(&v),
+                    })
+                    .map(|v| result.entry_points = Some(v)),
+            };
+            if let Err(reason) = resolved {
+                let snippet = module.snippet(range);
+                match snippet.is_empty() {
+                    true => unresolved(argument, reason),
+                    false => unresolved(argument, format!("{reason}: {snippet}")),
+                }
+            }
+        }
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.is_resolved() && self.unresolved.is_empty()
+    }
+
+    /// Whether anything was resolved, as opposed to there only being unresolved arguments.
+    pub fn is_resolved(&self) -> bool {
+        self.install_requires.is_some()
+            || self.extras_require.is_some()
+            || self.python_requires.is_some()
+            || self.entry_points.is_some()
+    }
+}
+
+fn string_list(value: &Literal) -> Result<Vec<String>, String> {
+    match value {
+        // A single requirement, or several on separate lines.
+        Literal::Str(s) => Ok(s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect()),
+        Literal::List(items) => items
+            .iter()
+            .map(|item| match item {
+                Literal::Str(s) => Ok(s.clone()),
+                _ => Err("Expected a list of strings".to_string()),
+            })
+            .collect(),
+        _ => Err("Expected a list of strings".to_string()),
+    }
+}
+
+fn string_list_dict(value: &Literal) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let Literal::Dict(items) = value else {
+        return Err("Expected a dict".to_string());
+    };
+    items
+        .iter()
+        .map(|(key, value)| match key {
+            Literal::Str(key) => Ok((key.clone(), string_list(value)?)),
+            _ => Err("Expected string keys".to_string()),
+        })
+        .collect()
+}
+
+fn entry_points_ini(text: &str) -> BTreeMap<String, Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut group = String::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            Some(name) => group = name.trim().to_string(),
+            None => groups
+                .entry(group.clone())
+                .or_default()
+                .push(line.to_string()),
+        }
+    }
+    groups
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Str(String),
+    List(Vec<Literal>),
+    Dict(Vec<(Literal, Literal)>),
+    /// Numbers, `None`, `True` and `False`, which are only useful as dict values nobody reads.
+    Constant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind<'a> {
+    Name(&'a str),
+    Str,
+    /// f-strings, and bytes, which aren't any use as a requirement.
+    OtherStr,
+    Number,
+    Op(&'a str),
+    Newline,
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: Kind<'a>,
+    start: usize,
+    end: usize,
+    /// The value of a string, with escapes processed.
+    value: Option<String>,
+}
+
+const OPERATORS: &[&str] = &[
+    "**=", "//=", ">>=", "<<=", "...", "**", "//", "==", "!=", "<=", ">=", "->", ":=", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "@=", ">>", "<<",
+];
+
+// Python's tokenizer, minus indentation, which doesn't matter for finding literals.
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, String> {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut depth = 0usize;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        match c {
+            b'#' => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            b'\\' if bytes.get(pos + 1) == Some(&b'\n') => pos += 2,
+            b'\\' if bytes.get(pos + 1..pos + 3) == Some(b"\r\n") => pos += 3,
+            b'\n' => {
+                // Newlines inside brackets don't end a statement.
+                if depth == 0
+                    && !matches!(
+                        tokens.last(),
+                        None | Some(Token {
+                            kind: Kind::Newline,
+                            ..
+                        })
+                    )
+                {
+                    tokens.push(Token {
+                        kind: Kind::Newline,
+                        start,
+                        end: pos + 1,
+                        value: None,
+                    });
+                }
+                pos += 1;
+            }
+            _ if c.is_ascii_whitespace() => pos += 1,
+            _ if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 => {
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric()
+                        || bytes[pos] == b'_'
+                        || bytes[pos] >= 0x80)
+                {
+                    pos += 1;
+                }
+                let word = &source[start..pos];
+                if pos < bytes.len()
+                    && matches!(bytes[pos], b'"' | b'\'')
+                    && word.len() <= 2
+                    && word.chars().all(|c| "rRbBuUfF".contains(c))
+                {
+                    let raw = word.contains(['r', 'R']);
+                    let (value, end) = read_string(source, pos, raw)?;
+                    pos = end;
+                    let literal = !word.contains(['b', 'B', 'f', 'F']);
+                    tokens.push(Token {
+                        kind: if literal { Kind::Str } else { Kind::OtherStr },
+                        start,
+                        end,
+                        value: Some(value),
+                    });
+                } else {
+                    tokens.push(Token {
+                        kind: Kind::Name(word),
+                        start,
+                        end: pos,
+                        value: None,
+                    });
+                }
+            }
+            b'"' | b'\'' => {
+                let (value, end) = read_string(source, pos, false)?;
+                pos = end;
+                tokens.push(Token {
+                    kind: Kind::Str,
+                    start,
+                    end,
+                    value: Some(value),
+                });
+            }
+            _ if c.is_ascii_digit()
+                || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric() || matches!(bytes[pos], b'.' | b'_'))
+                {
+                    pos += 1;
+                }
+                tokens.push(Token {
+                    kind: Kind::Number,
+                    start,
+                    end: pos,
+                    value: None,
+                });
+            }
+            _ => {
+                let op = OPERATORS
+                    .iter()
+                    .find(|op| source[pos..].starts_with(**op))
+                    .copied()
+                    .unwrap_or_else(|| &source[pos..pos + 1]);
+                match op {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                pos += op.len();
+                tokens.push(Token {
+                    kind: Kind::Op(op),
+                    start,
+                    end: pos,
+                    value: None,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Returns the value of the string starting with the quote at `pos`, and where it ends.
+fn read_string(source: &str, pos: usize, raw: bool) -> Result<(String, usize), String> {
+    let rest = &source[pos..];
+    let quote = &rest[..1];
+    let triple = quote.repeat(3);
+    let delimiter = if rest.starts_with(&triple) {
+        triple.as_str()
+    } else {
+        quote
+    };
+    let mut value = String::new();
+    let mut chars = rest[delimiter.len()..].char_indices();
+    while let Some((idx, c)) = chars.next() {
+        let remaining = &rest[delimiter.len() + idx..];
+        if remaining.starts_with(delimiter) {
+            return Ok((value, pos + delimiter.len() + idx + delimiter.len()));
+        }
+        match c {
+            '\n' if delimiter.len() == 1 => break,
+            '\\' => {
+                let Some((_, escaped)) = chars.next() else {
+                    break;
+                };
+                if raw {
+                    value.push('\\');
+                    value.push(escaped);
+                    continue;
+                }
+                match escaped {
+                    '\n' => {}
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    '0' => value.push('\0'),
+                    '\\' | '\'' | '"' => value.push(escaped),
+                    other => {
+                        value.push('\\');
+                        value.push(other);
+                    }
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    Err(format!("Unterminated string at byte {pos}"))
+}
+
+struct Module<'a> {
+    source: &'a str,
+    tokens: &'a [Token<'a>],
+    /// Module level variables assigned exactly once, and never modified, with their values.
+    variables: HashMap<&'a str, std::ops::Range<usize>>,
+}
+
+impl<'a> Module<'a> {
+    fn new(source: &'a str, tokens: &'a [Token<'a>]) -> Self {
+        let mut assignments: HashMap<&str, Vec<std::ops::Range<usize>>> = HashMap::new();
+        let mut modified = vec![];
+        for (idx, token) in tokens.iter().enumerate() {
+            let Kind::Name(name) = token.kind else {
+                continue;
+            };
+            // Indented assignments are inside a block, and may never run.
+            let statement_start = (idx == 0 || tokens[idx - 1].kind == Kind::Newline)
+                && (token.start == 0 || source.as_bytes()[token.start - 1] == b'\n');
+            match tokens.get(idx + 1).map(|t| t.kind) {
+                Some(Kind::Op("=")) if statement_start => {
+                    let end = tokens[idx + 2..]
+                        .iter()
+                        .position(|t| t.kind == Kind::Newline)
+                        .map_or(tokens.len(), |p| idx + 2 + p);
+                    assignments.entry(name).or_default().push(idx + 2..end);
+                }
+                // `requirements.append(...)`, `requirements += [...]` and so on.
+                Some(Kind::Op(op))
+                    if op == "." || (op.ends_with('=') && op.len() > 1 && op != "==") =>
+                {
+                    modified.push(name);
+                }
+                _ => {}
+            }
+        }
+        let variables = assignments
+            .into_iter()
+            .filter(|(name, values)| values.len() == 1 && !modified.contains(name))
+            .map(|(name, mut values)| (name, values.remove(0)))
+            .collect();
+        Module {
+            source,
+            tokens,
+            variables,
+        }
+    }
+
+    // The index of the opening parenthesis of the first `setup(...)` or `setuptools.setup(...)`
+    // that isn't a function definition.
+    fn setup_call(&self) -> Option<usize> {
+        self.tokens.windows(2).enumerate().find_map(|(idx, pair)| {
+            let is_call = pair[0].kind == Kind::Name("setup") && pair[1].kind == Kind::Op("(");
+            let is_definition = idx > 0 && self.tokens[idx - 1].kind == Kind::Name("def");
+            (is_call && !is_definition).then_some(idx + 1)
+        })
+    }
+
+    // The keyword (if any) and the token range of each argument in the call starting at `open`.
+    fn arguments(&self, open: usize) -> Vec<(Option<&'a str>, std::ops::Range<usize>)> {
+        let mut arguments = vec![];
+        let mut depth = 0;
+        let mut start = open + 1;
+        for idx in open..self.tokens.len() {
+            match self.tokens[idx].kind {
+                Kind::Op("(" | "[" | "{") => depth += 1,
+                Kind::Op(")" | "]" | "}") => depth -= 1,
+                _ => {}
+            }
+            let end_of_argument = (depth == 1 && self.tokens[idx].kind == Kind::Op(","))
+                || (depth == 0 && idx > open);
+            if end_of_argument {
+                if start < idx {
+                    arguments.push(self.argument(start..idx));
+                }
+                start = idx + 1;
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+        arguments
+    }
+
+    fn argument(&self, range: std::ops::Range<usize>) -> (Option<&'a str>, std::ops::Range<usize>) {
+        match (
+            &self.tokens[range.start].kind,
+            self.tokens.get(range.start + 1).map(|t| t.kind),
+        ) {
+            (Kind::Name(name), Some(Kind::Op("="))) => (Some(name), range.start + 2..range.end),
+            _ => (None, range),
+        }
+    }
+
+    fn snippet(&self, range: std::ops::Range<usize>) -> String {
+        // `setup(install_requires=, ...)` has nothing to show, and would otherwise slice backwards.
+        if range.is_empty() {
+            return String::new();
+        }
+        let (Some(first), Some(last)) =
+            (self.tokens.get(range.start), self.tokens.get(range.end - 1))
+        else {
+            return String::new();
+        };
+        let snippet = self.source[first.start..last.end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        match snippet.char_indices().nth(80) {
+            Some((idx, _)) => format!("{}...", &snippet[..idx]),
+            None => snippet,
+        }
+    }
+
+    fn evaluate(&self, range: std::ops::Range<usize>) -> Result<Literal, String> {
+        if range.is_empty() {
+            return Err("Missing value".to_string());
+        }
+        let mut parser = Parser {
+            module: self,
+            pos: range.start,
+            end: range.end,
+            depth: 0,
+        };
+        let value = parser.expression()?;
+        match parser.pos == parser.end {
+            true => Ok(value),
+            false => Err("Not a literal".to_string()),
+        }
+    }
+}
+
+struct Parser<'m, 'a> {
+    module: &'m Module<'a>,
+    pos: usize,
+    end: usize,
+    /// How many variables deep we are, so `a = b` and `b = a` can't loop forever.
+    depth: usize,
+}
+
+impl<'a> Parser<'_, 'a> {
+    fn peek(&self) -> Option<Kind<'a>> {
+        (self.pos < self.end).then(|| self.module.tokens[self.pos].kind)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(Kind::Op(o)) if o == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err("Not a literal".to_string()),
+        }
+    }
+
+    // Literals joined with `+`, which is how lists of requirements are often built up.
+    fn expression(&mut self) -> Result<Literal, String> {
+        let mut value = self.atom()?;
+        while self.peek() == Some(Kind::Op("+")) {
+            self.pos += 1;
+            value = match (value, self.atom()?) {
+                (Literal::Str(a), Literal::Str(b)) => Literal::Str(a + &b),
+                (Literal::List(mut a), Literal::List(b)) => {
+                    a.extend(b);
+                    Literal::List(a)
+                }
+                _ => return Err("Unsupported expression".to_string()),
+            };
+        }
+        Ok(value)
+    }
+
+    fn atom(&mut self) -> Result<Literal, String> {
+        let tokens = self.module.tokens;
+        let Some(kind) = self.peek() else {
+            return Err("Not a literal".to_string());
+        };
+        match kind {
+            Kind::Str => {
+                // Adjacent strings are concatenated.
+                let mut value = String::new();
+                while self.peek() == Some(Kind::Str) {
+                    value.push_str(tokens[self.pos].value.as_deref().unwrap_or_default());
+                    self.pos += 1;
+                }
+                Ok(Literal::Str(value))
+            }
+            Kind::Number => {
+                self.pos += 1;
+                Ok(Literal::Constant)
+            }
+            Kind::Name("None" | "True" | "False") => {
+                self.pos += 1;
+                Ok(Literal::Constant)
+            }
+            // `dict(console_scripts=[...])` is as common as a dict literal for entry points.
+            Kind::Name("dict") if self.next_is("(") => {
+                self.pos += 2;
+                let mut items = vec![];
+                while self.peek() != Some(Kind::Op(")")) {
+                    let Some(Kind::Name(key)) = self.peek() else {
+                        return Err("Not a literal".to_string());
+                    };
+                    self.pos += 1;
+                    self.expect("=")?;
+                    items.push((Literal::Str(key.to_string()), self.expression()?));
+                    if self.peek() != Some(Kind::Op(")")) {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Literal::Dict(items))
+            }
+            Kind::Name(name) => {
+                if self.next_is("(") || self.next_is(".") {
+                    return Err(format!("Computed by {name}"));
+                }
+                let Some(range) = self.module.variables.get(name).cloned() else {
+                    return Err(format!("{name} isn't a module level literal"));
+                };
+                if self.depth > 10 {
+                    return Err(format!("{name} refers back to itself"));
+                }
+                let mut parser = Parser {
+                    module: self.module,
+                    pos: range.start,
+                    end: range.end,
+                    depth: self.depth + 1,
+                };
+                let value = parser
+                    .expression()
+                    .map_err(|_| format!("{name} isn't a module level literal"))?;
+                if parser.pos != parser.end {
+                    return Err(format!("{name} isn't a module level literal"));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Kind::Op(open @ ("[" | "(")) => {
+                self.pos += 1;
+                let close = if open == "[" { "]" } else { ")" };
+                let mut items = vec![];
+                while self.peek() != Some(Kind::Op(close)) {
+                    items.push(self.expression()?);
+                    if self.peek() != Some(Kind::Op(close)) {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Literal::List(items))
+            }
+            Kind::Op("{") => {
+                self.pos += 1;
+                let mut items = vec![];
+                while self.peek() != Some(Kind::Op("}")) {
+                    let key = self.expression()?;
+                    self.expect(":")?;
+                    items.push((key, self.expression()?));
+                    if self.peek() != Some(Kind::Op("}")) {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Literal::Dict(items))
+            }
+            _ => Err("Not a literal".to_string()),
+        }
+    }
+
+    fn next_is(&self, op: &str) -> bool {
+        self.pos + 1 < self.end && self.module.tokens[self.pos + 1].kind == Kind::Op(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_keyword_values() {
+        for source in [
+            "setup(install_requires= , name='x')\n",
+            "setup(install_requires=,)\n",
+            "setup(install_requires=)\n",
+            "setup(python_requires=\n    \n)\n",
+        ] {
+            let analysis = SetupPy::analyse(source);
+            assert!(!analysis.is_resolved(), "{source:?}");
+            assert_eq!(analysis.unresolved.len(), 1, "{source:?}");
+            assert_eq!(analysis.unresolved[0].reason, "Missing value", "{source:?}");
+        }
+    }
+}
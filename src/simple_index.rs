@@ -0,0 +1,303 @@
+use crate::create_urls::{normalize_name, Digests, DownloadJob, FileMetadata, MetadataError};
+use crate::distribution::DistributionFile;
+use crate::job::APP_USER_AGENT;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use ureq::Agent;
+use url::Url;
+
+const SIMPLE_JSON: &str = "application/vnd.pypi.simple.v1+json";
+// Static mirrors (e.g. bandersnatch) write the JSON variant next to the HTML one.
+const STATIC_INDEX_FILES: &[&str] = &["index.v1_json", "index.json", "index.html"];
+
+/// A PEP 503 (HTML) or PEP 691 (JSON) simple repository, served over HTTP or written to a
+/// directory. Private indexes like devpi and local proxies all speak one or the other.
+pub struct SimpleIndex {
+    root: Url,
+    agent: Agent,
+}
+
+enum Page {
+    Json(String),
+    Html(String),
+}
+
+#[derive(Deserialize)]
+struct ProjectList {
+    projects: Vec<ProjectName>,
+}
+
+#[derive(Deserialize)]
+struct ProjectName {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ProjectPage {
+    files: Vec<SimpleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SimpleFile {
+    filename: String,
+    url: String,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    requires_python: Option<String>,
+    #[serde(default)]
+    yanked: Yanked,
+    size: Option<u64>,
+    upload_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Yanked {
+    Flag(bool),
+    Reason(String),
+}
+
+impl Default for Yanked {
+    fn default() -> Self {
+        Yanked::Flag(false)
+    }
+}
+
+impl SimpleIndex {
+    /// Returns `None` if `location` doesn't look like a simple index, i.e. it isn't an HTTP URL or
+    /// a directory containing a root index page.
+    pub fn open(location: &str) -> anyhow::Result<Option<Self>> {
+        let root = if location.starts_with("http://") || location.starts_with("https://") {
+            Url::parse(location)?
+        } else {
+            let path = Path::new(location);
+            if !STATIC_INDEX_FILES.iter().any(|f| path.join(f).is_file()) {
+                return Ok(None);
+            }
+            Url::from_file_path(fs::canonicalize(path)?)
+                .map_err(|_| anyhow!("Invalid index path {location}"))?
+        };
+        let agent = ureq::AgentBuilder::new()
+            .timeout_read(Duration::from_secs(30))
+            .user_agent(APP_USER_AGENT)
+            .build();
+        Ok(Some(SimpleIndex {
+            root: as_directory(root),
+            agent,
+        }))
+    }
+
+    pub fn projects(&self) -> anyhow::Result<Vec<String>> {
+        let (_, page) = self.fetch(&self.root)?;
+        Ok(match page {
+            Page::Json(body) => serde_json::from_str::<ProjectList>(&body)?
+                .projects
+                .into_iter()
+                .map(|p| p.name)
+                .collect(),
+            Page::Html(body) => anchors(&body).into_iter().map(|a| a.text).collect(),
+        })
+    }
+
+    /// Every file listed for a project, along with any that couldn't be turned into a job.
+    pub fn project_jobs(&self, name: &str) -> (Vec<DownloadJob>, Vec<MetadataError>) {
+        let normalized_name = normalize_name(name);
+        let page_url = match self.root.join(&normalized_name) {
+            Ok(url) => as_directory(url),
+            Err(e) => {
+                let error = MetadataError {
+                    path: PathBuf::from(self.root.as_str()),
+                    version: None,
+                    url: None,
+                    reason: format!("Invalid project name {name}: {e}"),
+                };
+                return (vec![], vec![error]);
+            }
+        };
+        let error = |url: Option<&str>, reason: String| MetadataError {
+            path: PathBuf::from(page_url.as_str()),
+            version: None,
+            url: url.map(str::to_string),
+            reason,
+        };
+
+        let files = self.fetch(&page_url).and_then(|(base, page)| {
+            Ok(match page {
+                Page::Json(body) => serde_json::from_str::<ProjectPage>(&body)?.files,
+                Page::Html(body) => anchors(&body)
+                    .into_iter()
+                    .map(|anchor| anchor.into_file())
+                    .collect(),
+            }
+            .into_iter()
+            .map(|file| (base.join(&file.url), file))
+            .collect::<Vec<_>>())
+        });
+        let files = match files {
+            Ok(files) => files,
+            Err(e) => return (vec![], vec![error(None, format!("{e:#}"))]),
+        };
+
+        let mut jobs = vec![];
+        let mut errors = vec![];
+        for (url, file) in files {
+            let mut url = match url {
+                Ok(url) => url,
+                Err(e) => {
+                    errors.push(error(Some(&file.url), e.to_string()));
+                    continue;
+                }
+            };
+            // The hash has already been taken from the fragment.
+            url.set_fragment(None);
+            // The simple API only lists files, so the version has to come from the filename.
+            let Some(distribution) = DistributionFile::parse(&file.filename, name) else {
+                errors.push(error(Some(url.as_str()), "Unknown version".to_string()));
+                continue;
+            };
+            let (yanked, yanked_reason) = match file.yanked {
+                Yanked::Flag(yanked) => (yanked, None),
+                Yanked::Reason(reason) => (true, Some(reason).filter(|r| !r.is_empty())),
+            };
+            jobs.push(DownloadJob {
+                name: name.to_string(),
+                normalized_name: normalized_name.clone(),
+                version: distribution.version.clone(),
+                // PEP 691 only added upload times in version 1.1, and PEP 503 never had them.
+                uploaded_on: file.upload_time,
+                metadata: FileMetadata {
+                    package_type: Some(distribution.package_type().to_string()),
+                    python_version: distribution.python_version(),
+                    requires_python: file.requires_python,
+                    size: file.size,
+                    yanked,
+                    yanked_reason,
+                    digests: Digests {
+                        md5: file.hashes.get("md5").cloned(),
+                        sha256: file.hashes.get("sha256").cloned(),
+                        blake2b_256: file.hashes.get("blake2b_256").cloned(),
+                    },
+                },
+                filename: Some(file.filename),
+                url,
+                aliases: vec![],
+                siblings: vec![],
+            });
+        }
+        (jobs, errors)
+    }
+
+    // Returns the URL relative links on the page should be resolved against, and the page.
+    fn fetch(&self, url: &Url) -> anyhow::Result<(Url, Page)> {
+        if url.scheme() == "file" {
+            let dir = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid index path {url}"))?;
+            let file = STATIC_INDEX_FILES
+                .iter()
+                .map(|f| dir.join(f))
+                .find(|f| f.is_file())
+                .with_context(|| format!("No index page in {}", dir.display()))?;
+            let body = fs::read_to_string(&file)?;
+            let page = match file.extension() {
+                Some(e) if e == "html" => Page::Html(body),
+                _ => Page::Json(body),
+            };
+            return Ok((url.clone(), page));
+        }
+
+        let response = self
+            .agent
+            .get(url.as_str())
+            .set("Accept", &format!("{SIMPLE_JSON}, text/html;q=0.1"))
+            .call()
+            .with_context(|| format!("Error fetching {url}"))?;
+        let base = Url::parse(response.get_url())?;
+        let is_json = response.content_type() == SIMPLE_JSON;
+        let body = response.into_string()?;
+        Ok((
+            base,
+            if is_json {
+                Page::Json(body)
+            } else {
+                Page::Html(body)
+            },
+        ))
+    }
+}
+
+fn as_directory(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    url
+}
+
+struct Anchor {
+    text: String,
+    attributes: HashMap<String, String>,
+}
+
+impl Anchor {
+    fn into_file(self) -> SimpleFile {
+        let href = self.attributes.get("href").cloned().unwrap_or_default();
+        // PEP 503 puts the hash in the fragment, e.g. `#sha256=...`.
+        let hashes = href
+            .split_once('#')
+            .and_then(|(_, fragment)| fragment.split_once('='))
+            .map(|(name, value)| HashMap::from([(name.to_string(), value.to_string())]))
+            .unwrap_or_default();
+        SimpleFile {
+            filename: self.text,
+            url: href,
+            hashes,
+            requires_python: self.attributes.get("data-requires-python").cloned(),
+            yanked: match self.attributes.get("data-yanked") {
+                None => Yanked::Flag(false),
+                Some(reason) => Yanked::Reason(reason.clone()),
+            },
+            size: None,
+            upload_time: None,
+        }
+    }
+}
+
+// Simple index pages are just a list of anchors, so a regex is enough to pull them out.
+fn anchors(html: &str) -> Vec<Anchor> {
+    static ANCHOR: OnceLock<Regex> = OnceLock::new();
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    let anchor = ANCHOR.get_or_init(|| Regex::new(r"(?is)<a\s([^>]*)>(.*?)</a>").unwrap());
+    let attribute = ATTRIBUTE
+        .get_or_init(|| Regex::new(r#"(?s)([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+    anchor
+        .captures_iter(html)
+        .map(|a| Anchor {
+            text: unescape(a[2].trim()),
+            attributes: attribute
+                .captures_iter(&a[1])
+                .map(|attr| {
+                    let value = attr.get(2).or_else(|| attr.get(3)).unwrap().as_str();
+                    (attr[1].to_lowercase(), unescape(value))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
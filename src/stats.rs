@@ -0,0 +1,210 @@
+use crate::create_urls::{read_package_metadata, DownloadJob};
+use crate::partition::{partition, PartitionManifestEntry, PartitionOptions};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use jwalk::WalkDir;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum StatsFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorpusStats {
+    pub months: Vec<MonthStats>,
+    pub largest_by_bytes: Vec<PackageStats>,
+    pub largest_by_files: Vec<PackageStats>,
+    pub partitions: Vec<PartitionManifestEntry>,
+    pub metadata_errors: usize,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct MonthStats {
+    pub month: String,
+    pub uploads: usize,
+    pub packages: usize,
+    pub bytes: u64,
+    pub package_types: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PackageStats {
+    pub name: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Summarise a tree of PyPI metadata files, and how `create-urls` would partition it.
+pub fn corpus_stats(
+    dir: PathBuf,
+    top: usize,
+    split: usize,
+    partition_options: PartitionOptions,
+    format: StatsFormat,
+) -> anyhow::Result<()> {
+    let files: Vec<_> = WalkDir::new(dir)
+        .min_depth(2)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .collect();
+    let packages: Vec<_> = files
+        .into_par_iter()
+        .map(|entry| read_package_metadata(&entry.path()))
+        .collect();
+
+    let mut metadata_errors = 0;
+    let mut jobs = vec![];
+    for (package_jobs, errors) in packages {
+        metadata_errors += errors.len();
+        jobs.extend(package_jobs);
+    }
+    jobs.sort();
+
+    let stats = CorpusStats {
+        months: month_stats(&jobs),
+        largest_by_bytes: largest_packages(&jobs, top, |p| p.bytes),
+        largest_by_files: largest_packages(&jobs, top, |p| p.files as u64),
+        partitions: partition(jobs, split, &partition_options)
+            .into_iter()
+            .map(|(name, jobs)| PartitionManifestEntry::new(format!("{name}.jsonl"), &jobs))
+            .collect(),
+        metadata_errors,
+    };
+
+    match format {
+        StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        StatsFormat::Table => print_table(&stats),
+    }
+    Ok(())
+}
+
+fn month_stats(jobs: &[DownloadJob]) -> Vec<MonthStats> {
+    let mut months: BTreeMap<String, (MonthStats, HashSet<&str>)> = BTreeMap::new();
+    for job in jobs {
+        let month = match job.uploaded_on {
+            Some(time) => time.format("%Y-%m").to_string(),
+            None => "unknown".to_string(),
+        };
+        let (stats, packages) = months.entry(month.clone()).or_insert_with(|| {
+            let stats = MonthStats {
+                month,
+                ..Default::default()
+            };
+            (stats, HashSet::new())
+        });
+        stats.uploads += 1;
+        stats.bytes += job.metadata.size.unwrap_or_default();
+        let package_type = job.metadata.package_type.as_deref().unwrap_or("unknown");
+        *stats
+            .package_types
+            .entry(package_type.to_string())
+            .or_default() += 1;
+        packages.insert(&job.name);
+    }
+    months
+        .into_values()
+        .map(|(mut stats, packages)| {
+            stats.packages = packages.len();
+            stats
+        })
+        .collect()
+}
+
+fn largest_packages(
+    jobs: &[DownloadJob],
+    top: usize,
+    key: impl Fn(&PackageStats) -> u64,
+) -> Vec<PackageStats> {
+    let mut packages: HashMap<&str, PackageStats> = HashMap::new();
+    for job in jobs {
+        let stats = packages.entry(&job.name).or_insert_with(|| PackageStats {
+            name: job.name.clone(),
+            files: 0,
+            bytes: 0,
+        });
+        stats.files += 1;
+        stats.bytes += job.metadata.size.unwrap_or_default();
+    }
+    packages
+        .into_values()
+        .sorted_by(|a, b| key(b).cmp(&key(a)).then_with(|| a.name.cmp(&b.name)))
+        .take(top)
+        .collect()
+}
+
+fn print_table(stats: &CorpusStats) {
+    println!(
+        "{:<8} {:>10} {:>10} {:>12}  package types",
+        "month", "uploads", "packages", "size"
+    );
+    for month in &stats.months {
+        let package_types = month
+            .package_types
+            .iter()
+            .map(|(name, count)| format!("{name}={count}"))
+            .join(" ");
+        println!(
+            "{:<8} {:>10} {:>10} {:>12}  {package_types}",
+            month.month,
+            month.uploads,
+            month.packages,
+            format_bytes(month.bytes)
+        );
+    }
+
+    for (title, packages) in [
+        ("Largest packages by size", &stats.largest_by_bytes),
+        ("Largest packages by file count", &stats.largest_by_files),
+    ] {
+        println!();
+        println!("{title}");
+        for package in packages {
+            println!(
+                "{:<40} {:>10} {:>12}",
+                package.name,
+                package.files,
+                format_bytes(package.bytes)
+            );
+        }
+    }
+
+    println!();
+    println!("Projected partitions");
+    for partition in &stats.partitions {
+        println!(
+            "{:<40} {:>10} {:>12}  {} - {}",
+            partition.file,
+            partition.jobs,
+            format_bytes(partition.bytes),
+            format_date(partition.earliest),
+            format_date(partition.latest)
+        );
+    }
+
+    if stats.metadata_errors != 0 {
+        println!();
+        println!("{} metadata errors", stats.metadata_errors);
+    }
+}
+
+fn format_date(time: Option<DateTime<Utc>>) -> String {
+    time.map_or("unknown".to_string(), |t| t.format("%Y-%m-%d").to_string())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", units[unit])
+}
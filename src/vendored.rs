@@ -0,0 +1,151 @@
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+
+// Projects that are commonly copied wholesale into other distributions.
+const VENDORED_PROJECTS: &[&str] = &[
+    "certifi",
+    "chardet",
+    "charset_normalizer",
+    "distlib",
+    "idna",
+    "packaging",
+    "pkg_resources",
+    "pyparsing",
+    "requests",
+    "setuptools",
+    "six",
+    "six.py",
+    "tomli",
+    "typing_extensions.py",
+    "urllib3",
+];
+
+const VENDOR_DIRECTORIES: &[&str] = &[
+    "_vendor",
+    "vendor",
+    "vendored",
+    "_vendored",
+    "extern",
+    "_extern",
+    "third_party",
+    "thirdparty",
+];
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum VendoredKind {
+    VirtualEnv,
+    SitePackages,
+    BuildArtifact,
+    Tox,
+    NodeModules,
+    VendoredPackage,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+pub enum VendoredAction {
+    /// Leave vendored files out of the release, listing them in the manifest
+    Skip,
+    /// Import vendored files but tag them in the manifest
+    Tag,
+    /// Import vendored files as if they were normal files
+    Import,
+}
+
+/// Blob IDs of files known to be copies of a vendored project, loaded from a file containing one
+/// hex object ID per line. Anything after the ID (like the project name) is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct KnownBlobs(Arc<HashSet<Oid>>);
+
+impl KnownBlobs {
+    pub fn contains(&self, oid: &Oid) -> bool {
+        self.0.contains(oid)
+    }
+}
+
+pub fn load_known_blobs(path: &str) -> anyhow::Result<KnownBlobs> {
+    let contents = fs::read_to_string(path)?;
+    let oids = contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| Oid::from_str(l.split_whitespace().next().unwrap()))
+        .collect::<Result<_, _>>()?;
+    Ok(KnownBlobs(Arc::new(oids)))
+}
+
+/// Work out if a path within an archive looks like part of a virtualenv, a build directory or a
+/// vendored copy of another project, rather than code that belongs to the release itself.
+pub fn detect_vendored(path: &str) -> Option<VendoredKind> {
+    let segments: Vec<_> = path.split('/').collect();
+    // The last segment is the file name, which never tells us anything.
+    let directories = &segments[..segments.len() - 1];
+
+    for (idx, segment) in directories.iter().enumerate() {
+        let next = segments.get(idx + 1).copied().unwrap_or_default();
+        match *segment {
+            "venv" | ".venv" => return Some(VendoredKind::VirtualEnv),
+            "site-packages" | "dist-packages" => return Some(VendoredKind::SitePackages),
+            "lib" if is_python_lib_dir(next) => return Some(VendoredKind::SitePackages),
+            "build" if next == "lib" || next.starts_with("lib.") || next.starts_with("bdist.") => {
+                return Some(VendoredKind::BuildArtifact)
+            }
+            ".tox" | ".nox" => return Some(VendoredKind::Tox),
+            "node_modules" => return Some(VendoredKind::NodeModules),
+            s if VENDOR_DIRECTORIES.contains(&s) && VENDORED_PROJECTS.contains(&next) => {
+                return Some(VendoredKind::VendoredPackage)
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// lib/python3.11/, lib/python2.7/ but not lib/python/, which plenty of projects use for their code.
+fn is_python_lib_dir(segment: &str) -> bool {
+    segment
+        .strip_prefix("python")
+        .and_then(|v| v.chars().next())
+        .map(|c| c.is_ascii_digit())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_modules() {
+        assert_eq!(
+            detect_vendored("pkg-1.0/node_modules/node-gyp/gyp/pylib/gyp/common.py"),
+            Some(VendoredKind::NodeModules)
+        );
+    }
+
+    #[test]
+    fn vendored_packages() {
+        assert_eq!(
+            detect_vendored("pip/_vendor/requests/api.py"),
+            Some(VendoredKind::VendoredPackage)
+        );
+        assert_eq!(detect_vendored("pkg/_vendor/mylib/api.py"), None);
+    }
+
+    #[test]
+    fn python_lib_dirs() {
+        assert_eq!(
+            detect_vendored("env/lib/python3.11/foo/__init__.py"),
+            Some(VendoredKind::SitePackages)
+        );
+        assert_eq!(detect_vendored("pkg/lib/python/foo.py"), None);
+    }
+
+    #[test]
+    fn file_names_are_ignored() {
+        assert_eq!(detect_vendored("pkg/venv"), None);
+        assert_eq!(detect_vendored("node_modules"), None);
+    }
+}
@@ -0,0 +1,288 @@
+use regex::Regex;
+use std::cmp::Ordering;
+use std::sync::OnceLock;
+
+/// A release version. PyPI only started enforcing PEP 440 in 2016, so older releases are full of
+/// versions like `2004-03-01` or `0.1-beta-fix` that have to be kept as they are.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Version {
+    /// Versions that aren't valid under PEP 440. These sort before every valid version, as they
+    /// did in setuptools, and by their text amongst themselves.
+    Legacy(String),
+    Pep440(Pep440Version),
+}
+
+#[derive(Debug, Clone)]
+pub struct Pep440Version {
+    pub epoch: u64,
+    pub release: Vec<u64>,
+    pub pre: Option<(PreRelease, u64)>,
+    pub post: Option<u64>,
+    pub dev: Option<u64>,
+    pub local: Option<Vec<LocalSegment>>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    ReleaseCandidate,
+}
+
+/// Local version labels compare numeric segments as numbers, and sort them after text segments.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LocalSegment {
+    Text(String),
+    Number(u64),
+}
+
+// The pattern from PEP 440's appendix, which also accepts the alternative spellings that are
+// normalised away (`1.0-alpha.1`, `v1.0`, `1.0-1` and so on).
+const VERSION_PATTERN: &str = r"(?ix)
+    ^\s*v?
+    (?:(?P<epoch>[0-9]+)!)?
+    (?P<release>[0-9]+(?:\.[0-9]+)*)
+    (?P<pre>
+        [-_.]?
+        (?P<pre_l>alpha|a|beta|b|preview|pre|c|rc)
+        [-_.]?
+        (?P<pre_n>[0-9]+)?
+    )?
+    (?P<post>
+        (?:-(?P<post_n1>[0-9]+))
+        |
+        (?:
+            [-_.]?
+            (?P<post_l>post|rev|r)
+            [-_.]?
+            (?P<post_n2>[0-9]+)?
+        )
+    )?
+    (?P<dev>
+        [-_.]?
+        dev
+        [-_.]?
+        (?P<dev_n>[0-9]+)?
+    )?
+    (?:\+(?P<local>[a-z0-9]+(?:[-_.][a-z0-9]+)*))?
+    \s*$";
+
+impl Version {
+    pub fn parse(version: &str) -> Self {
+        Pep440Version::parse(version)
+            .map(Version::Pep440)
+            .unwrap_or_else(|| Version::Legacy(version.to_string()))
+    }
+
+    /// Alpha, beta, release candidate and development releases.
+    pub fn is_prerelease(&self) -> bool {
+        match self {
+            Version::Legacy(_) => false,
+            Version::Pep440(version) => version.pre.is_some() || version.dev.is_some(),
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, Version::Legacy(_))
+    }
+}
+
+impl Pep440Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let captures = PATTERN
+            .get_or_init(|| Regex::new(VERSION_PATTERN).unwrap())
+            .captures(version)?;
+        // Numbers too large for a u64 (the odd timestamp) make the whole version legacy.
+        let number = |name: &str| match captures.name(name) {
+            None => Some(None),
+            Some(n) => n.as_str().parse().ok().map(Some),
+        };
+
+        let release = captures["release"]
+            .split('.')
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        let pre = match captures.name("pre_l") {
+            None => None,
+            Some(label) => {
+                let kind = match label.as_str().to_lowercase().as_str() {
+                    "a" | "alpha" => PreRelease::Alpha,
+                    "b" | "beta" => PreRelease::Beta,
+                    _ => PreRelease::ReleaseCandidate,
+                };
+                Some((kind, number("pre_n")?.unwrap_or(0)))
+            }
+        };
+        let post = match captures.name("post") {
+            None => None,
+            Some(_) => Some(number("post_n1")?.or(number("post_n2")?).unwrap_or(0)),
+        };
+        let dev = match captures.name("dev") {
+            None => None,
+            Some(_) => Some(number("dev_n")?.unwrap_or(0)),
+        };
+        let local = captures.name("local").map(|local| {
+            local
+                .as_str()
+                .to_lowercase()
+                .split(['-', '_', '.'])
+                .map(|segment| match segment.parse() {
+                    Ok(n) => LocalSegment::Number(n),
+                    Err(_) => LocalSegment::Text(segment.to_string()),
+                })
+                .collect()
+        });
+
+        Some(Pep440Version {
+            epoch: number("epoch")?.unwrap_or(0),
+            release,
+            pre,
+            post,
+            dev,
+            local,
+        })
+    }
+
+    // The ordering PEP 440 describes, as implemented by `packaging`: trailing zeros in the release
+    // don't matter, a release without a pre-release comes after its pre-releases, and a bare dev
+    // release comes before all of them.
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        u64,
+        &[u64],
+        (u8, u64),
+        Option<u64>,
+        (bool, u64),
+        Option<&[LocalSegment]>,
+    ) {
+        let trailing_zeros = self.release.iter().rev().take_while(|n| **n == 0).count();
+        let release = &self.release[..self.release.len() - trailing_zeros];
+        let pre = match (self.pre, self.post, self.dev) {
+            (None, None, Some(_)) => (0, 0),
+            (None, _, _) => (u8::MAX, 0),
+            (Some((kind, n)), _, _) => (kind as u8 + 1, n),
+        };
+        (
+            self.epoch,
+            release,
+            pre,
+            self.post,
+            (self.dev.is_none(), self.dev.unwrap_or(0)),
+            self.local.as_deref(),
+        )
+    }
+}
+
+impl Ord for Pep440Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl PartialOrd for Pep440Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pep440Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pep440Version {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            let (a, b) = (Version::parse(pair[0]), Version::parse(pair[1]));
+            assert!(a < b, "{} should sort before {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn pep_440_ordering() {
+        // The example ordering from PEP 440's summary of permitted suffixes.
+        assert_ascending(&[
+            "1.0.dev456",
+            "1.0a1",
+            "1.0a2.dev456",
+            "1.0a12.dev456",
+            "1.0a12",
+            "1.0b1.dev456",
+            "1.0b2",
+            "1.0b2.post345.dev456",
+            "1.0b2.post345",
+            "1.0rc1.dev456",
+            "1.0rc1",
+            "1.0",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+5",
+            "1.0.post456.dev34",
+            "1.0.post456",
+            "1.0.15",
+            "1.1.dev1",
+        ]);
+    }
+
+    #[test]
+    fn epochs() {
+        assert_ascending(&["2024.1", "1!0.1", "1!1.0", "2!0.0.1"]);
+    }
+
+    #[test]
+    fn trailing_zeros() {
+        assert_eq!(Version::parse("1.0"), Version::parse("1.0.0"));
+        assert_eq!(Version::parse("1"), Version::parse("1.0.0.0"));
+        assert_ne!(Version::parse("1.0"), Version::parse("1.0.1"));
+    }
+
+    #[test]
+    fn alternative_spellings() {
+        for (spelling, normalised) in [
+            ("v1.0", "1.0"),
+            ("1.0-alpha.1", "1.0a1"),
+            ("1.0c1", "1.0rc1"),
+            ("1.0preview2", "1.0rc2"),
+            ("1.0-1", "1.0.post1"),
+            ("1.0.rev", "1.0.post0"),
+            ("1.0-dev", "1.0.dev0"),
+            ("1.0+Ubuntu_1", "1.0+ubuntu.1"),
+        ] {
+            assert_eq!(
+                Version::parse(spelling),
+                Version::parse(normalised),
+                "{spelling}"
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_versions() {
+        for version in [
+            "2004-03-01",
+            "0.1-beta-fix",
+            "1.0.x",
+            "99999999999999999999999",
+        ] {
+            assert!(Version::parse(version).is_legacy(), "{version}");
+        }
+        assert_ascending(&["0.1-beta-fix", "2004-03-01", "0.0.1.dev1"]);
+    }
+
+    #[test]
+    fn prereleases() {
+        assert!(Version::parse("1.0a1").is_prerelease());
+        assert!(Version::parse("1.0.dev1").is_prerelease());
+        assert!(!Version::parse("1.0.post1").is_prerelease());
+        assert!(!Version::parse("2004-03-01").is_prerelease());
+    }
+}